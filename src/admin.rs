@@ -0,0 +1,607 @@
+// 管理接口：运行时注册、替换、删除声音，清除合成结果缓存，需要在配置文件中设置 admin_token
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use actix_multipart::Multipart;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use gpt_sovits_rs::voice_manager::{VoiceModel, MODEL_FILE, REF_AUDIO_FILE, REF_TEXT_FILE};
+use gpt_sovits_rs::GPTSovits;
+use serde::Deserialize;
+use serde_json::json;
+use tokio_stream::StreamExt;
+
+use crate::cache::{self, CacheManager, Purge};
+use crate::{ApiError, AppState, RequestId};
+
+// 上传的参考音频和文本字段的大小上限
+const MAX_REF_AUDIO_BYTES: usize = 20 * 1024 * 1024;
+const MAX_TEXT_FIELD_BYTES: usize = 64 * 1024;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/voices", web::post().to(create_voice))
+            .route("/voices/{name}", web::put().to(replace_voice))
+            .route("/voices/{name}", web::delete().to(delete_voice))
+            .route("/cache", web::delete().to(purge_cache))
+            .route(
+                "/cache/speakers/{speaker}",
+                web::delete().to(purge_speaker_cache),
+            )
+            .route("/cache/entries", web::delete().to(purge_cache_entry)),
+    );
+}
+
+// multipart 表单：name、ref_audio（wav 文件）、ref_text、model_path（服务器上的模型路径）
+#[derive(Default)]
+struct VoiceUpload {
+    name: Option<String>,
+    ref_audio: Option<Vec<u8>>,
+    ref_text: Option<String>,
+    model_path: Option<String>,
+}
+
+async fn read_upload(
+    mut payload: Multipart,
+    request_id: &RequestId,
+) -> Result<VoiceUpload, ApiError> {
+    let mut upload = VoiceUpload::default();
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| ApiError::bad_request(request_id, e))?;
+        let name = field.name().unwrap_or_default().to_string();
+        let limit = match name.as_str() {
+            "ref_audio" => MAX_REF_AUDIO_BYTES,
+            "name" | "ref_text" | "model_path" => MAX_TEXT_FIELD_BYTES,
+            _ => {
+                log::debug!("[{}] 忽略未知字段: {}", request_id.as_str(), name);
+                continue;
+            }
+        };
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| ApiError::bad_request(request_id, e))?;
+            if data.len() + chunk.len() > limit {
+                return Err(ApiError::new(
+                    request_id,
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "payload_too_large",
+                    format!("{} is larger than {} bytes", name, limit),
+                ));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        if name == "ref_audio" {
+            upload.ref_audio = Some(data);
+            continue;
+        }
+        let value = String::from_utf8(data)
+            .map_err(|_| ApiError::bad_request(request_id, format!("{} is not UTF-8", name)))?;
+        match name.as_str() {
+            "name" => upload.name = Some(value.trim().to_string()),
+            "ref_text" => upload.ref_text = Some(value),
+            _ => upload.model_path = Some(value.trim().to_string()),
+        }
+    }
+
+    Ok(upload)
+}
+
+// 只校验 Bearer token，未配置 admin_token 时不会注册管理接口
+fn authorize(req: &HttpRequest, data: &AppState, request_id: &RequestId) -> Result<(), ApiError> {
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match (&data.admin_token, token) {
+        (Some(expected), Some(token)) if token_matches(expected, token) => Ok(()),
+        _ => Err(ApiError::new(
+            request_id,
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "invalid admin token",
+        )),
+    }
+}
+
+// 比较两边的 sha256，耗时与 token 的内容无关，无法逐字节猜出 token
+fn token_matches(expected: &str, token: &str) -> bool {
+    use sha2::{Digest, Sha256};
+
+    let expected = Sha256::digest(expected.as_bytes());
+    let token = Sha256::digest(token.as_bytes());
+    expected
+        .iter()
+        .zip(token.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+// 正在安装的声音名，同名的注册或替换请求同时只能有一个在进行
+#[derive(Default)]
+pub struct InstallLocks(Mutex<HashSet<String>>);
+
+// 持有期间其他请求无法安装同名声音，drop 时释放
+struct InstallGuard<'a> {
+    locks: &'a InstallLocks,
+    name: String,
+}
+
+impl InstallLocks {
+    fn lock(&self, name: &str, request_id: &RequestId) -> Result<InstallGuard<'_>, ApiError> {
+        let mut names = self.0.lock().unwrap();
+        if !names.insert(name.to_string()) {
+            return Err(ApiError::new(
+                request_id,
+                StatusCode::CONFLICT,
+                "voice_busy",
+                format!("voice {} is being installed by another request", name),
+            ));
+        }
+        Ok(InstallGuard {
+            locks: self,
+            name: name.to_string(),
+        })
+    }
+}
+
+impl Drop for InstallGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.0.lock().unwrap().remove(&self.name);
+    }
+}
+
+// 声音名就是目录名，不允许路径分隔符和隐藏目录
+fn validate_name(name: &str, request_id: &RequestId) -> Result<(), ApiError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control());
+    if valid {
+        Ok(())
+    } else {
+        Err(ApiError::bad_request(
+            request_id,
+            format!("invalid voice name: {:?}", name),
+        ))
+    }
+}
+
+fn validate_model_path(model_path: &str, request_id: &RequestId) -> Result<(), ApiError> {
+    if Path::new(model_path).is_file() {
+        Ok(())
+    } else {
+        Err(ApiError::bad_request(
+            request_id,
+            format!("model not found: {}", model_path),
+        ))
+    }
+}
+
+// 模型文件通常很大，优先硬链接，跨文件系统时再复制
+fn link_model(model_path: &Path, dir: &Path) -> std::io::Result<()> {
+    let target = dir.join(MODEL_FILE);
+    if fs::hard_link(model_path, &target).is_err() {
+        fs::copy(model_path, &target)?;
+    }
+    Ok(())
+}
+
+// 复制目录中除模型以外的文件（voice.toml、各情感的参考音频和文本等）
+fn copy_voice_files(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_voice_files(&entry.path(), &target)?;
+        } else if entry.file_name() != MODEL_FILE {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+// 把上传内容写入 dir，缺少的字段沿用 existing 中的文件
+fn write_voice_dir(
+    dir: &Path,
+    upload: &VoiceUpload,
+    existing: Option<&VoiceModel>,
+) -> anyhow::Result<()> {
+    match existing {
+        Some(existing) => copy_voice_files(&existing.path, dir)?,
+        None => fs::create_dir_all(dir)?,
+    }
+
+    match (&upload.ref_audio, existing) {
+        (Some(ref_audio), _) => fs::write(dir.join(REF_AUDIO_FILE), ref_audio)?,
+        (None, Some(_)) => {}
+        (None, None) => return Err(anyhow::anyhow!("ref_audio is required")),
+    }
+
+    match (&upload.ref_text, existing) {
+        (Some(ref_text), _) => fs::write(dir.join(REF_TEXT_FILE), ref_text)?,
+        (None, Some(_)) => {}
+        (None, None) => return Err(anyhow::anyhow!("ref_text is required")),
+    }
+
+    match (&upload.model_path, existing) {
+        (Some(model_path), _) => link_model(Path::new(model_path), dir)?,
+        (None, Some(existing)) => link_model(&existing.model_path(), dir)?,
+        (None, None) => return Err(anyhow::anyhow!("model_path is required")),
+    }
+
+    Ok(())
+}
+
+// 先在隐藏目录中准备好文件并检查，再移动到 voices/<name> 并从那里加载
+// 移动或加载失败时恢复原来的目录，正在使用的声音不受影响
+fn install_voice(
+    gpt_sovits: &GPTSovits,
+    voices_dir: &Path,
+    name: &str,
+    upload: &VoiceUpload,
+    existing: Option<&VoiceModel>,
+) -> anyhow::Result<()> {
+    let target = voices_dir.join(name);
+    let staging = voices_dir.join(format!(".{}.new", name));
+    let backup = voices_dir.join(format!(".{}.old", name));
+    let _ = fs::remove_dir_all(&staging);
+
+    let staged = VoiceModel::new(name, staging.clone());
+    let checked = write_voice_dir(&staging, upload, existing).and_then(|_| staged.validate());
+    if let Err(e) = checked {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let backed_up = match swap_dir(&staging, &target, &backup) {
+        Ok(backed_up) => backed_up,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.into());
+        }
+    };
+    // 加载成功后才替换正在使用的声音
+    if let Err(e) = VoiceModel::new(name, target.clone()).load(gpt_sovits) {
+        restore_dir(&target, &backup, backed_up);
+        return Err(e);
+    }
+    if backed_up {
+        if let Err(e) = fs::remove_dir_all(&backup) {
+            log::warn!("删除旧的声音目录 {} 失败: {}", backup.display(), e);
+        }
+    }
+    Ok(())
+}
+
+// 用 staging 替换 target，原目录先移到 backup，返回是否移动了原目录
+// 替换失败时把原目录移回
+fn swap_dir(staging: &Path, target: &Path, backup: &Path) -> std::io::Result<bool> {
+    let backed_up = target.exists();
+    if backed_up {
+        let _ = fs::remove_dir_all(backup);
+        fs::rename(target, backup)?;
+    }
+    if let Err(e) = fs::rename(staging, target) {
+        if backed_up {
+            let _ = fs::rename(backup, target);
+        }
+        return Err(e);
+    }
+    Ok(backed_up)
+}
+
+// 新目录加载失败时删除它，把原目录移回
+fn restore_dir(target: &Path, backup: &Path, backed_up: bool) {
+    let _ = fs::remove_dir_all(target);
+    if backed_up {
+        if let Err(e) = fs::rename(backup, target) {
+            log::error!("恢复声音目录 {} 失败: {}", target.display(), e);
+        }
+    }
+}
+
+fn add_voice(data: &AppState, request_id: &RequestId, name: &str) -> Result<(), ApiError> {
+    data.voice_manager
+        .write()
+        .map_err(|e| ApiError::internal(request_id, format!("获取 voice_manager 写锁失败: {}", e)))?
+        .add_voice(name);
+    Ok(())
+}
+
+// 注册新声音：POST /admin/voices
+async fn create_voice(
+    req: HttpRequest,
+    request_id: RequestId,
+    payload: Multipart,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let upload = read_upload(payload, &request_id).await?;
+
+    let name = upload
+        .name
+        .clone()
+        .ok_or_else(|| ApiError::bad_request(&request_id, "name is required"))?;
+    validate_name(&name, &request_id)?;
+    if upload.ref_audio.is_none() || upload.ref_text.is_none() {
+        return Err(ApiError::bad_request(
+            &request_id,
+            "ref_audio and ref_text are required",
+        ));
+    }
+    let model_path = upload
+        .model_path
+        .as_deref()
+        .ok_or_else(|| ApiError::bad_request(&request_id, "model_path is required"))?;
+    validate_model_path(model_path, &request_id)?;
+
+    // 在检查声音是否存在之前占住这个名字，同名的并发请求不会都通过检查
+    let _install = data.install_locks.lock(&name, &request_id)?;
+    let voices_dir = {
+        let voice_manager = data.voice_manager.read().map_err(|e| {
+            ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+        })?;
+        voice_manager.voices_dir().to_path_buf()
+    };
+    if data.gpt_sovits.get_speaker(&name).is_some() || voices_dir.join(&name).exists() {
+        return Err(ApiError::new(
+            &request_id,
+            StatusCode::CONFLICT,
+            "voice_exists",
+            format!("voice already exists: {}", name),
+        ));
+    }
+
+    let gpt_sovits = data.gpt_sovits.clone();
+    let voice_name = name.clone();
+    let result =
+        web::block(move || install_voice(&gpt_sovits, &voices_dir, &voice_name, &upload, None))
+            .await
+            .map_err(|e| ApiError::internal(&request_id, e))?;
+    result.map_err(|e| {
+        ApiError::new(
+            &request_id,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_voice",
+            format!("failed to load voice {}: {}", name, e),
+        )
+    })?;
+    add_voice(&data, &request_id, &name)?;
+    log::info!("[{}] 已注册声音: {}", request_id.as_str(), name);
+
+    Ok(HttpResponse::Created().json(json!({ "name": name })))
+}
+
+// 替换已有声音：PUT /admin/voices/{name}，未上传的字段沿用原来的文件
+async fn replace_voice(
+    req: HttpRequest,
+    request_id: RequestId,
+    path: web::Path<String>,
+    payload: Multipart,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let name = path.into_inner();
+    let upload = read_upload(payload, &request_id).await?;
+    if let Some(model_path) = &upload.model_path {
+        validate_model_path(model_path, &request_id)?;
+    }
+    let _install = data.install_locks.lock(&name, &request_id)?;
+
+    let (existing, voices_dir) = {
+        let voice_manager = data.voice_manager.read().map_err(|e| {
+            ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+        })?;
+        let existing = voice_manager.get_voice(&name).cloned().ok_or_else(|| {
+            ApiError::not_found(&request_id, format!("voice not found: {}", name))
+        })?;
+        (existing, voice_manager.voices_dir().to_path_buf())
+    };
+
+    let gpt_sovits = data.gpt_sovits.clone();
+    let voice_name = name.clone();
+    let result = web::block(move || {
+        install_voice(
+            &gpt_sovits,
+            &voices_dir,
+            &voice_name,
+            &upload,
+            Some(&existing),
+        )
+    })
+    .await
+    .map_err(|e| ApiError::internal(&request_id, e))?;
+    result.map_err(|e| {
+        ApiError::new(
+            &request_id,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_voice",
+            format!("failed to replace voice {}: {}", name, e),
+        )
+    })?;
+    // 重新登记，更新声音文件的指纹，目录监听不会再重复加载
+    add_voice(&data, &request_id, &name)?;
+    log::info!("[{}] 已替换声音: {}", request_id.as_str(), name);
+
+    Ok(HttpResponse::Ok().json(json!({ "name": name })))
+}
+
+// 删除声音：DELETE /admin/voices/{name}，正在进行的请求会用旧模型完成
+async fn delete_voice(
+    req: HttpRequest,
+    request_id: RequestId,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let name = path.into_inner();
+    // 与注册、替换互斥，不会删掉正在安装的目录
+    let _install = data.install_locks.lock(&name, &request_id)?;
+
+    let voice_model = data
+        .voice_manager
+        .write()
+        .map_err(|e| {
+            ApiError::internal(&request_id, format!("获取 voice_manager 写锁失败: {}", e))
+        })?
+        .remove_voice(&name)
+        .ok_or_else(|| ApiError::not_found(&request_id, format!("voice not found: {}", name)))?;
+    voice_model.unload(&data.gpt_sovits);
+
+    if let Err(e) = fs::remove_dir_all(&voice_model.path) {
+        log::warn!(
+            "[{}] 删除声音目录 {} 失败: {}",
+            request_id.as_str(),
+            voice_model.path.display(),
+            e
+        );
+    }
+    log::info!("[{}] 已删除声音: {}", request_id.as_str(), name);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// 删除缓存文件在阻塞线程中进行
+async fn purge(
+    cache: &Arc<Mutex<CacheManager>>,
+    request_id: &RequestId,
+    target: impl FnOnce(&Mutex<CacheManager>) -> usize + Send + 'static,
+) -> Result<usize, ApiError> {
+    let cache = cache.clone();
+    web::block(move || target(&cache))
+        .await
+        .map_err(|e| ApiError::internal(request_id, e))
+}
+
+// 清除全部缓存
+async fn purge_cache(
+    req: HttpRequest,
+    request_id: RequestId,
+    data: web::Data<AppState>,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let purged = purge(&cache, &request_id, |cache| cache::purge(cache, Purge::All)).await?;
+    Ok(HttpResponse::Ok().json(json!({ "purged": purged })))
+}
+
+// 清除一个声音的全部缓存，声音被替换后旧结果本来就不会命中，这里用于释放空间
+async fn purge_speaker_cache(
+    req: HttpRequest,
+    request_id: RequestId,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let speaker = path.into_inner();
+    let purged = purge(&cache, &request_id, move |cache| {
+        cache::purge(cache, Purge::Speaker(&speaker))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "purged": purged })))
+}
+
+#[derive(Deserialize)]
+struct CacheEntryQuery {
+    speaker: String,
+    text: String,
+    // 不指定时删除所有情感的结果
+    emotion: Option<String>,
+}
+
+// 删除某个声音某段文本的缓存，不论合成参数
+async fn purge_cache_entry(
+    req: HttpRequest,
+    request_id: RequestId,
+    query: web::Query<CacheEntryQuery>,
+    data: web::Data<AppState>,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &data, &request_id)?;
+    let CacheEntryQuery {
+        speaker,
+        text,
+        emotion,
+    } = query.into_inner();
+    let purged = {
+        let (speaker, text) = (speaker.clone(), text.clone());
+        purge(&cache, &request_id, move |cache| {
+            let target = Purge::Text {
+                speaker: &speaker,
+                text: &text,
+                emotion: emotion.as_deref(),
+            };
+            cache::purge(cache, target)
+        })
+        .await?
+    };
+    if purged == 0 {
+        return Err(ApiError::not_found(
+            &request_id,
+            format!("no cached audio for {}: {}", speaker, text),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "purged": purged })))
+}
+
+#[test]
+fn test_install_locks() {
+    assert!(token_matches("secret", "secret"));
+    assert!(!token_matches("secret", "secreT"));
+    assert!(!token_matches("secret", ""));
+
+    let locks = InstallLocks::default();
+    let request_id = RequestId("test".to_string());
+    let guard = locks.lock("alice", &request_id).unwrap();
+    assert!(locks.lock("alice", &request_id).is_err());
+    assert!(locks.lock("bob", &request_id).is_ok());
+    drop(guard);
+    assert!(locks.lock("alice", &request_id).is_ok());
+}
+
+#[test]
+fn test_swap_dir() {
+    let dir = std::env::temp_dir().join(format!("gpt_sovits_swap_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (staging, target, backup) = (
+        dir.join(".alice.new"),
+        dir.join("alice"),
+        dir.join(".alice.old"),
+    );
+    let marker = |path: &Path| fs::read_to_string(path.join("marker")).ok();
+    let stage = |content: &str| {
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("marker"), content).unwrap();
+    };
+
+    // 新声音没有原目录
+    stage("v1");
+    assert!(!swap_dir(&staging, &target, &backup).unwrap());
+    assert_eq!(marker(&target).as_deref(), Some("v1"));
+
+    // 替换后加载失败，恢复原目录
+    stage("v2");
+    assert!(swap_dir(&staging, &target, &backup).unwrap());
+    assert_eq!(marker(&target).as_deref(), Some("v2"));
+    restore_dir(&target, &backup, true);
+    assert_eq!(marker(&target).as_deref(), Some("v1"));
+    assert!(!backup.exists());
+
+    // 移动新目录失败时原目录留在原处
+    assert!(swap_dir(&staging, &target, &backup).is_err());
+    assert_eq!(marker(&target).as_deref(), Some("v1"));
+    assert!(!backup.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,268 @@
+//! Minimal FLAC encoder: mono, 16 bit, fixed predictors and Rice coded
+//! residuals. Enough to get lossless output about half the size of WAV.
+
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+const MAX_RICE_PARAM: u32 = 14;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        let mut zeros = zeros;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The "UTF-8" style variable length integer used for frame numbers.
+fn write_utf8_number(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.write(n, 8);
+        return;
+    }
+    let mut len = 2;
+    while len < 7 && n >= 1u64 << (5 * len + 1) {
+        len += 1;
+    }
+    let lead = (0xFFu64 << (8 - len)) & 0xFF;
+    w.write(lead | (n >> (6 * (len - 1))), 8);
+    for i in (0..len - 1).rev() {
+        w.write(0x80 | ((n >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+fn fixed_residual(block: &[i32], order: usize) -> Vec<i64> {
+    let s = |i: usize| block[i] as i64;
+    (order..block.len())
+        .map(|i| match order {
+            0 => s(i),
+            1 => s(i) - s(i - 1),
+            2 => s(i) - 2 * s(i - 1) + s(i - 2),
+            3 => s(i) - 3 * s(i - 1) + 3 * s(i - 2) - s(i - 3),
+            _ => s(i) - 4 * s(i - 1) + 6 * s(i - 2) - 4 * s(i - 3) + s(i - 4),
+        })
+        .collect()
+}
+
+/// Returns the best Rice parameter and the encoded size in bits.
+fn best_rice_param(residual: &[i64]) -> (u32, u64) {
+    let folded: Vec<u64> = residual.iter().map(|r| zigzag(*r)).collect();
+    (0..=MAX_RICE_PARAM)
+        .map(|k| {
+            let bits = folded.iter().map(|u| (u >> k) + 1 + k as u64).sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap_or((0, 0))
+}
+
+fn write_subframe(w: &mut BitWriter, block: &[i32]) {
+    let verbatim_bits = block.len() as u64 * BITS_PER_SAMPLE as u64;
+
+    let best = (0..=4usize.min(block.len().saturating_sub(1)))
+        .map(|order| {
+            let residual = fixed_residual(block, order);
+            let (k, bits) = best_rice_param(&residual);
+            (
+                order,
+                residual,
+                k,
+                bits + (order as u64) * BITS_PER_SAMPLE as u64,
+            )
+        })
+        .min_by_key(|(_, _, _, bits)| *bits);
+
+    match best {
+        Some((order, residual, k, bits)) if bits < verbatim_bits => {
+            // subframe header: zero bit, FIXED type 001xxx, no wasted bits
+            w.write(0, 1);
+            w.write(0b001000 | order as u64, 6);
+            w.write(0, 1);
+            for sample in &block[..order] {
+                w.write_signed(*sample as i64, BITS_PER_SAMPLE);
+            }
+            // Rice coding with 4 bit parameters, partition order 0
+            w.write(0, 2);
+            w.write(0, 4);
+            w.write(k as u64, 4);
+            for r in residual {
+                let u = zigzag(r);
+                w.write_unary(u >> k);
+                w.write(u, k);
+            }
+        }
+        _ => {
+            w.write(0, 1);
+            w.write(0b000001, 6);
+            w.write(0, 1);
+            for sample in block {
+                w.write_signed(*sample as i64, BITS_PER_SAMPLE);
+            }
+        }
+    }
+}
+
+fn sample_rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        8000 => 0b0100,
+        16000 => 0b0101,
+        22050 => 0b0110,
+        24000 => 0b0111,
+        32000 => 0b1000,
+        44100 => 0b1001,
+        48000 => 0b1010,
+        96000 => 0b1011,
+        // read it from STREAMINFO
+        _ => 0b0000,
+    }
+}
+
+fn write_frame(out: &mut Vec<u8>, block: &[i32], frame_number: u64, sample_rate: u32) {
+    let mut w = BitWriter::new();
+    w.write(0b11111111111110, 14);
+    w.write(0, 1);
+    // fixed block size stream
+    w.write(0, 1);
+    // block size is stored as a 16 bit value after the frame number
+    w.write(0b0111, 4);
+    w.write(sample_rate_code(sample_rate), 4);
+    // mono
+    w.write(0b0000, 4);
+    // 16 bits per sample
+    w.write(0b100, 3);
+    w.write(0, 1);
+    write_utf8_number(&mut w, frame_number);
+    w.write(block.len() as u64 - 1, 16);
+    let crc = crc8(&w.bytes);
+    w.write(crc as u64, 8);
+
+    write_subframe(&mut w, block);
+    w.align();
+    let crc = crc16(&w.bytes);
+    w.write(crc as u64, 16);
+
+    out.extend_from_slice(&w.bytes);
+}
+
+/// Encodes 16 bit mono samples as a FLAC file.
+pub fn encode(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(samples.len());
+    out.extend_from_slice(b"fLaC");
+
+    let block_size = BLOCK_SIZE.min(samples.len().max(16));
+    let mut w = BitWriter::new();
+    // last metadata block, STREAMINFO, 34 bytes
+    w.write(1, 1);
+    w.write(0, 7);
+    w.write(34, 24);
+    w.write(block_size as u64, 16);
+    w.write(block_size as u64, 16);
+    // unknown min/max frame size
+    w.write(0, 24);
+    w.write(0, 24);
+    w.write(sample_rate as u64, 20);
+    w.write(0, 3);
+    w.write(BITS_PER_SAMPLE as u64 - 1, 5);
+    w.write(samples.len() as u64 >> 32, 4);
+    w.write(samples.len() as u64 & 0xFFFF_FFFF, 32);
+    // MD5 left unset, which means unknown
+    for _ in 0..4 {
+        w.write(0, 32);
+    }
+    out.extend_from_slice(&w.bytes);
+
+    let samples: Vec<i32> = samples.iter().map(|s| *s as i32).collect();
+    for (frame_number, block) in samples.chunks(block_size).enumerate() {
+        write_frame(&mut out, block, frame_number as u64, sample_rate);
+    }
+    out
+}
+
+#[test]
+fn test_utf8_number() {
+    let mut w = BitWriter::new();
+    write_utf8_number(&mut w, 0x7F);
+    write_utf8_number(&mut w, 0x80);
+    write_utf8_number(&mut w, 0x800);
+    assert_eq!(w.bytes, vec![0x7F, 0xC2, 0x80, 0xE0, 0xA0, 0x80]);
+}
+
+#[test]
+fn test_crc() {
+    assert_eq!(crc8(b"123456789"), 0xF4);
+    assert_eq!(crc16(b"123456789"), 0xFEE8);
+}
@@ -0,0 +1,380 @@
+use std::str::FromStr;
+
+use resample::Resampler;
+
+pub mod flac;
+#[cfg(feature = "opus")]
+pub mod opus;
+pub mod resample;
+pub mod stretch;
+
+/// Sample rate of the audio produced by the exported models.
+pub const SAMPLE_RATE: u32 = 32000;
+
+/// Sample rate of the G.711 (μ-law / A-law) telephony outputs.
+const TELEPHONY_RATE: u32 = 8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    /// WAV, 16 bit PCM
+    Wav16,
+    /// WAV, 24 bit PCM
+    Wav24,
+    /// WAV, 32 bit float
+    WavF32,
+    /// headerless 16 bit little endian PCM
+    Pcm16,
+    Flac,
+    OggOpus,
+    /// headerless 8 kHz G.711 μ-law
+    MuLaw,
+    /// headerless 8 kHz G.711 A-law
+    ALaw,
+}
+
+impl FromStr for AudioFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" | "wav16" => Ok(AudioFormat::Wav16),
+            "wav24" => Ok(AudioFormat::Wav24),
+            "wav32" | "wav_f32" | "wavf32" => Ok(AudioFormat::WavF32),
+            "pcm" | "raw" => Ok(AudioFormat::Pcm16),
+            "flac" => Ok(AudioFormat::Flac),
+            #[cfg(feature = "opus")]
+            "opus" | "ogg" => Ok(AudioFormat::OggOpus),
+            #[cfg(not(feature = "opus"))]
+            "opus" | "ogg" => Err(anyhow::anyhow!(
+                "{} output needs gpt_sovits_rs built with the `opus` feature",
+                s
+            )),
+            "mulaw" | "ulaw" | "pcmu" => Ok(AudioFormat::MuLaw),
+            "alaw" | "pcma" => Ok(AudioFormat::ALaw),
+            _ => Err(anyhow::anyhow!("unsupported audio format: {}", s)),
+        }
+    }
+}
+
+impl AudioFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav16 | AudioFormat::Wav24 | AudioFormat::WavF32 => "audio/wav",
+            AudioFormat::Pcm16 => "audio/pcm",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::OggOpus => "audio/ogg",
+            AudioFormat::MuLaw => "audio/basic",
+            AudioFormat::ALaw => "audio/x-alaw-basic",
+        }
+    }
+
+    /// Rate `encode` writes for input at `sample_rate`: 8 kHz for G.711,
+    /// the input rate otherwise.
+    pub fn output_rate(&self, sample_rate: u32) -> u32 {
+        match self {
+            AudioFormat::MuLaw | AudioFormat::ALaw => TELEPHONY_RATE,
+            _ => sample_rate,
+        }
+    }
+
+    /// Encodes mono samples in `[-1, 1]`.
+    pub fn encode(&self, samples: &[f32], sample_rate: u32) -> anyhow::Result<Vec<u8>> {
+        self.encode_at(samples, sample_rate, self.output_rate(sample_rate))
+    }
+
+    /// Like `encode`, resampling to `output_rate` first.
+    pub fn encode_at(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        output_rate: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        let resampled;
+        let samples = match self {
+            AudioFormat::Flac | AudioFormat::OggOpus if output_rate != sample_rate => {
+                resampled = Resampler::resample(samples, sample_rate, output_rate);
+                &resampled[..]
+            }
+            _ => samples,
+        };
+        match self {
+            AudioFormat::Flac => Ok(flac::encode(&to_i16(samples), output_rate)),
+            #[cfg(feature = "opus")]
+            AudioFormat::OggOpus => opus::encode(samples, output_rate),
+            #[cfg(not(feature = "opus"))]
+            AudioFormat::OggOpus => Err(anyhow::anyhow!(
+                "opus output needs gpt_sovits_rs built with the `opus` feature"
+            )),
+            _ => {
+                let encoder = self
+                    .stream_encoder_at(sample_rate, output_rate)
+                    .expect("headerless and WAV formats can be streamed");
+                Ok(encoder.encode_all(samples))
+            }
+        }
+    }
+
+    /// Returns an encoder that writes the output piece by piece, for the
+    /// formats that do not need the total length up front.
+    pub fn stream_encoder(&self, sample_rate: u32) -> Option<StreamEncoder> {
+        self.stream_encoder_at(sample_rate, self.output_rate(sample_rate))
+    }
+
+    /// Like `stream_encoder`, resampling to `output_rate` on the way.
+    pub fn stream_encoder_at(&self, sample_rate: u32, output_rate: u32) -> Option<StreamEncoder> {
+        if let AudioFormat::Flac | AudioFormat::OggOpus = self {
+            return None;
+        }
+        Some(StreamEncoder {
+            format: *self,
+            sample_rate: output_rate,
+            resampler: (sample_rate != output_rate)
+                .then(|| Resampler::new(sample_rate, output_rate)),
+        })
+    }
+}
+
+/// Encoder for WAV, raw PCM and G.711 output that can be fed chunk by chunk.
+#[derive(Debug, Clone)]
+pub struct StreamEncoder {
+    format: AudioFormat,
+    /// rate of the encoded output
+    sample_rate: u32,
+    resampler: Option<Resampler>,
+}
+
+impl StreamEncoder {
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// WAV header with unknown lengths, empty for headerless formats.
+    pub fn header(&self) -> Vec<u8> {
+        match self.format {
+            AudioFormat::Wav16 => wav_header(self.sample_rate, 16, false, None),
+            AudioFormat::Wav24 => wav_header(self.sample_rate, 24, false, None),
+            AudioFormat::WavF32 => wav_header(self.sample_rate, 32, true, None),
+            _ => vec![],
+        }
+    }
+
+    pub fn encode(&mut self, samples: &[f32]) -> Vec<u8> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let samples = resampler.process(samples);
+                encode_samples(self.format, &samples)
+            }
+            None => encode_samples(self.format, samples),
+        }
+    }
+
+    /// Flushes any samples held back by the resampler.
+    pub fn finish(&mut self) -> Vec<u8> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let samples = resampler.finish();
+                encode_samples(self.format, &samples)
+            }
+            None => vec![],
+        }
+    }
+
+    fn encode_all(mut self, samples: &[f32]) -> Vec<u8> {
+        let mut data = self.encode(samples);
+        data.extend(self.finish());
+
+        let (bits, is_float) = match self.format {
+            AudioFormat::Wav16 => (16, false),
+            AudioFormat::Wav24 => (24, false),
+            AudioFormat::WavF32 => (32, true),
+            _ => return data,
+        };
+        let mut wav = wav_header(self.sample_rate, bits, is_float, Some(data.len() as u32));
+        wav.extend(data);
+        wav
+    }
+}
+
+fn encode_samples(format: AudioFormat, samples: &[f32]) -> Vec<u8> {
+    match format {
+        AudioFormat::Wav16 | AudioFormat::Pcm16 => to_i16(samples)
+            .into_iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect(),
+        AudioFormat::Wav24 => samples
+            .iter()
+            .flat_map(|s| {
+                let s = (s.clamp(-1.0, 1.0) * 8388607.0).round() as i32;
+                let [b0, b1, b2, _] = s.to_le_bytes();
+                [b0, b1, b2]
+            })
+            .collect(),
+        AudioFormat::WavF32 => samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        AudioFormat::MuLaw => to_i16(samples).into_iter().map(linear_to_ulaw).collect(),
+        AudioFormat::ALaw => to_i16(samples).into_iter().map(linear_to_alaw).collect(),
+        AudioFormat::Flac | AudioFormat::OggOpus => unreachable!("not a sample format"),
+    }
+}
+
+pub fn to_i16(samples: &[f32]) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+        .collect()
+}
+
+/// Mono WAV header. `data_len` is `None` when streaming, in which case the
+/// RIFF and data lengths are set to `0xFFFFFFFF`.
+fn wav_header(
+    sample_rate: u32,
+    bits_per_sample: u16,
+    is_float: bool,
+    data_len: Option<u32>,
+) -> Vec<u8> {
+    let block_align = bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+    // float data needs the extended fmt chunk and a fact chunk
+    let (fmt_len, format_tag, fact_len) = if is_float {
+        (18u32, 3u16, 12u32)
+    } else {
+        (16, 1, 0)
+    };
+    let riff_len = data_len
+        .map(|len| 4 + (8 + fmt_len) + fact_len + 8 + len)
+        .unwrap_or(u32::MAX);
+
+    let mut header = Vec::with_capacity(58);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&riff_len.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&fmt_len.to_le_bytes());
+    header.extend_from_slice(&format_tag.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits_per_sample.to_le_bytes());
+    if is_float {
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(b"fact");
+        header.extend_from_slice(&4u32.to_le_bytes());
+        let frames = data_len
+            .map(|len| len / block_align as u32)
+            .unwrap_or(u32::MAX);
+        header.extend_from_slice(&frames.to_le_bytes());
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.unwrap_or(u32::MAX).to_le_bytes());
+    header
+}
+
+/// G.711 μ-law, as in the reference implementation.
+fn linear_to_ulaw(sample: i16) -> u8 {
+    const BIAS: i32 = 0x84;
+    const CLIP: i32 = 32635;
+
+    let mut s = sample as i32;
+    let sign = if s < 0 {
+        s = -s;
+        0x80
+    } else {
+        0
+    };
+    let s = s.min(CLIP) + BIAS;
+    let exponent = 31 - ((s >> 7) as u32).leading_zeros() as i32;
+    let mantissa = (s >> (exponent + 3)) & 0x0F;
+    !((sign | (exponent << 4) | mantissa) as u8)
+}
+
+/// G.711 A-law, as in the reference implementation.
+fn linear_to_alaw(sample: i16) -> u8 {
+    const SEG_END: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+    let mut pcm = (sample as i32) >> 3;
+    let mask = if pcm >= 0 {
+        0xD5
+    } else {
+        pcm = -pcm - 1;
+        0x55
+    };
+    let seg = SEG_END.iter().position(|end| pcm <= *end).unwrap_or(8) as i32;
+    if seg >= 8 {
+        return (0x7F ^ mask) as u8;
+    }
+    let mantissa = if seg < 2 { pcm >> 1 } else { pcm >> seg } & 0x0F;
+    (((seg << 4) | mantissa) ^ mask) as u8
+}
+
+#[test]
+fn test_g711() {
+    assert_eq!(linear_to_ulaw(0), 0xFF);
+    assert_eq!(linear_to_ulaw(-1), 0x7F);
+    assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+    assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+
+    assert_eq!(linear_to_alaw(0), 0xD5);
+    assert_eq!(linear_to_alaw(-8), 0x55);
+    assert_eq!(linear_to_alaw(i16::MAX), 0xAA);
+    assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+}
+
+#[test]
+fn test_wav_header() {
+    let wav = AudioFormat::Wav16.encode(&[0.0; 100], SAMPLE_RATE).unwrap();
+    assert_eq!(wav.len(), 44 + 200);
+    assert_eq!(&wav[4..8], &(36u32 + 200).to_le_bytes());
+
+    let wav = AudioFormat::WavF32
+        .encode(&[0.0; 100], SAMPLE_RATE)
+        .unwrap();
+    assert_eq!(wav.len(), 58 + 400);
+    assert_eq!(&wav[4..8], &(wav.len() as u32 - 8).to_le_bytes());
+}
+
+#[test]
+fn test_stream_encoder() {
+    let samples: Vec<f32> = (0..3000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+    for format in [AudioFormat::Wav16, AudioFormat::Pcm16, AudioFormat::MuLaw] {
+        let whole = format.encode(&samples, SAMPLE_RATE).unwrap();
+
+        let mut encoder = format.stream_encoder(SAMPLE_RATE).unwrap();
+        let header = encoder.header();
+        let mut streamed = vec![];
+        for chunk in samples.chunks(700) {
+            streamed.extend(encoder.encode(chunk));
+        }
+        streamed.extend(encoder.finish());
+
+        // same data, only the header lengths are unknown while streaming
+        assert_eq!(streamed, whole[header.len()..], "{:?}", format);
+        if !header.is_empty() {
+            assert_eq!(&header[4..8], &u32::MAX.to_le_bytes());
+            assert_eq!(&header[header.len() - 4..], &u32::MAX.to_le_bytes());
+        }
+    }
+}
+
+#[test]
+fn test_encode_at() {
+    let samples = vec![0.25; 3200];
+    // 100 ms at 24 kHz, 16 bit
+    let pcm = AudioFormat::Pcm16
+        .encode_at(&samples, SAMPLE_RATE, 24000)
+        .unwrap();
+    assert!((pcm.len() as i64 - 4800).abs() <= 4, "{}", pcm.len());
+    let wav = AudioFormat::Wav16
+        .encode_at(&samples, SAMPLE_RATE, 24000)
+        .unwrap();
+    assert_eq!(&wav[24..28], &24000u32.to_le_bytes());
+    assert_eq!(wav.len() - 44, pcm.len());
+}
+
+#[test]
+fn test_parse_format() {
+    assert_eq!("WAV".parse::<AudioFormat>().unwrap(), AudioFormat::Wav16);
+    assert_eq!("pcmu".parse::<AudioFormat>().unwrap(), AudioFormat::MuLaw);
+    assert!("mp3".parse::<AudioFormat>().is_err());
+    // 没有 opus 特性时在解析时就拒绝，不等到推理完成后编码才失败
+    assert_eq!("ogg".parse::<AudioFormat>().is_ok(), cfg!(feature = "opus"));
+}
@@ -0,0 +1,86 @@
+//! Ogg/Opus output, only built with the `opus` feature since it links libopus.
+
+use audiopus::{coder::Encoder, Application, Bitrate, Channels, SampleRate};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+use super::resample::Resampler;
+
+/// Opus always runs at 48 kHz here, 20 ms frames.
+const OPUS_RATE: u32 = 48000;
+const FRAME_SIZE: usize = 960;
+const BITRATE: i32 = 32000;
+const SERIAL: u32 = 0x4750_5453;
+
+fn opus_head(pre_skip: u16, input_rate: u32) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    // mono
+    head.push(1);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&input_rate.to_le_bytes());
+    // output gain
+    head.extend_from_slice(&0i16.to_le_bytes());
+    // channel mapping family
+    head.push(0);
+    head
+}
+
+fn opus_tags() -> Vec<u8> {
+    let vendor = concat!("gpt_sovits_rs ", env!("CARGO_PKG_VERSION"));
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    // no user comments
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+/// Encodes mono samples as an Ogg/Opus file.
+pub fn encode(samples: &[f32], sample_rate: u32) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Voip)?;
+    encoder.set_bitrate(Bitrate::BitsPerSecond(BITRATE))?;
+    let pre_skip = encoder.lookahead()? as usize;
+
+    let mut pcm = Resampler::resample(samples, sample_rate, OPUS_RATE);
+    let length = pcm.len();
+    // flush the encoder delay, then pad to whole frames
+    let padded = (length + pre_skip).div_ceil(FRAME_SIZE).max(1) * FRAME_SIZE;
+    pcm.resize(padded, 0.0);
+
+    let mut writer = PacketWriter::new(Vec::new());
+    writer.write_packet(
+        opus_head(pre_skip as u16, sample_rate).into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+    writer.write_packet(
+        opus_tags().into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+
+    // granule positions count decoded samples including the pre-skip, the
+    // last one trims the padding
+    let frames = pcm.len() / FRAME_SIZE;
+    let mut packet = vec![0u8; 4000];
+    for (i, frame) in pcm.chunks(FRAME_SIZE).enumerate() {
+        let n = encoder.encode_float(frame, &mut packet)?;
+        let (end_info, granule) = if i + 1 == frames {
+            (PacketWriteEndInfo::EndStream, pre_skip + length)
+        } else {
+            (PacketWriteEndInfo::NormalPacket, (i + 1) * FRAME_SIZE)
+        };
+        writer.write_packet(
+            packet[..n].to_vec().into_boxed_slice(),
+            SERIAL,
+            end_info,
+            granule as u64,
+        )?;
+    }
+
+    Ok(writer.into_inner())
+}
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc kernel on each side, at the cutoff frequency.
+const ZERO_CROSSINGS: f64 = 16.0;
+
+/// Streaming windowed-sinc resampler for mono audio.
+///
+/// Input can be pushed in arbitrary chunks, the output is the same as
+/// resampling the concatenated input in one go.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    /// input samples per output sample
+    step: f64,
+    /// cutoff relative to the input Nyquist frequency
+    cutoff: f64,
+    /// half width of the kernel in input samples
+    half_width: usize,
+
+    buffer: Vec<f32>,
+    /// absolute index of `buffer[0]` in the input
+    offset: u64,
+    /// absolute index of the next output sample
+    next_out: u64,
+    input_len: u64,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let step = from_rate as f64 / to_rate as f64;
+        let cutoff = (to_rate as f64 / from_rate as f64).min(1.0) * 0.95;
+        let half_width = (ZERO_CROSSINGS / cutoff).ceil() as usize;
+
+        Self {
+            step,
+            cutoff,
+            half_width,
+            buffer: Vec::new(),
+            offset: 0,
+            next_out: 0,
+            input_len: 0,
+        }
+    }
+
+    /// Resamples a whole buffer.
+    pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+        if from_rate == to_rate {
+            return samples.to_vec();
+        }
+        let mut resampler = Self::new(from_rate, to_rate);
+        let mut output = resampler.process(samples);
+        output.extend(resampler.finish());
+        output
+    }
+
+    /// Pushes input samples and returns the output samples that are complete.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.buffer.extend_from_slice(samples);
+        self.input_len += samples.len() as u64;
+        self.drain(self.input_len)
+    }
+
+    /// Flushes the output that is still waiting for right-hand context.
+    pub fn finish(&mut self) -> Vec<f32> {
+        let total = (self.input_len as f64 / self.step).ceil() as u64;
+        let padding = vec![0f32; self.half_width + 1];
+        self.buffer.extend_from_slice(&padding);
+        let end = self.offset + self.buffer.len() as u64;
+
+        let mut output = self.drain(end);
+        let extra = (self.next_out.saturating_sub(total)) as usize;
+        output.truncate(output.len().saturating_sub(extra));
+        output
+    }
+
+    fn drain(&mut self, available: u64) -> Vec<f32> {
+        let mut output = vec![];
+        loop {
+            let t = self.next_out as f64 * self.step;
+            let center = t.floor() as i64;
+            if center + self.half_width as i64 >= available as i64 {
+                break;
+            }
+            output.push(self.sample_at(t));
+            self.next_out += 1;
+        }
+
+        // keep the left-hand context of the next output sample
+        let t = self.next_out as f64 * self.step;
+        let keep_from = (t.floor() as i64 - self.half_width as i64).max(0) as u64;
+        if keep_from > self.offset {
+            let n = ((keep_from - self.offset) as usize).min(self.buffer.len());
+            self.buffer.drain(..n);
+            self.offset += n as u64;
+        }
+        output
+    }
+
+    fn sample_at(&self, t: f64) -> f32 {
+        let center = t.floor() as i64;
+        let half = self.half_width as i64;
+        let mut sum = 0f64;
+        for k in (center - half + 1)..=(center + half) {
+            if k < self.offset as i64 {
+                continue;
+            }
+            let i = (k - self.offset as i64) as usize;
+            let Some(sample) = self.buffer.get(i) else {
+                break;
+            };
+            let x = t - k as f64;
+            sum += *sample as f64 * self.kernel(x);
+        }
+        sum as f32
+    }
+
+    fn kernel(&self, x: f64) -> f64 {
+        let half = self.half_width as f64;
+        if x.abs() >= half {
+            return 0.0;
+        }
+        let y = x * self.cutoff;
+        let sinc = if y == 0.0 {
+            1.0
+        } else {
+            (PI * y).sin() / (PI * y)
+        };
+        // Blackman window
+        let n = (x + half) / (2.0 * half);
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+        self.cutoff * sinc * window
+    }
+}
+
+#[test]
+fn test_resample_length() {
+    let samples = vec![0.5f32; 32000];
+    assert_eq!(Resampler::resample(&samples, 32000, 8000).len(), 8000);
+    assert_eq!(Resampler::resample(&samples, 32000, 48000).len(), 48000);
+}
+
+#[test]
+fn test_resample_chunked() {
+    let samples: Vec<f32> = (0..10000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+    let whole = Resampler::resample(&samples, 32000, 8000);
+
+    let mut resampler = Resampler::new(32000, 8000);
+    let mut chunked = vec![];
+    for chunk in samples.chunks(777) {
+        chunked.extend(resampler.process(chunk));
+    }
+    chunked.extend(resampler.finish());
+
+    assert_eq!(whole.len(), chunked.len());
+    for (a, b) in whole.iter().zip(chunked.iter()) {
+        assert!((a - b).abs() < 1e-6);
+    }
+}
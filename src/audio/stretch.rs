@@ -0,0 +1,124 @@
+use std::f32::consts::PI;
+
+/// Analysis window, long enough to hold two periods of a low male voice.
+const WINDOW_MS: usize = 30;
+/// How far a frame may move from its nominal position to line up with the
+/// previous one, at least half the longest pitch period.
+const TOLERANCE_MS: usize = 10;
+/// Step of the coarse alignment search, refined sample by sample afterwards.
+const COARSE_STEP: usize = 4;
+
+/// Changes the duration of mono audio without changing its pitch, `speed`
+/// 2.0 halves the length. Uses WSOLA: Hann-windowed frames are overlap-added
+/// at a fixed output hop, each taken from near its nominal input position
+/// where it best continues the waveform of the previous frame.
+///
+/// Designed for speech at 0.5 to 2.0, works further out with more
+/// artifacts.
+pub fn time_stretch(samples: &[f32], sample_rate: u32, speed: f32) -> Vec<f32> {
+    if samples.is_empty() || (speed - 1.0).abs() < 1e-3 {
+        return samples.to_vec();
+    }
+
+    let sample_rate = sample_rate as usize;
+    let window = (sample_rate * WINDOW_MS / 1000).max(4) & !1;
+    let hop = window / 2;
+    let tolerance = sample_rate * TOLERANCE_MS / 1000;
+    // periodic Hann, sums to one at 50% overlap
+    let hann: Vec<f32> = (0..window)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / window as f32).cos())
+        .collect();
+
+    let out_len = (samples.len() as f64 / speed as f64).round() as usize;
+    let mut input = samples.to_vec();
+    input.resize(samples.len() + window + tolerance + 2 * hop, 0.0);
+    let mut output = vec![0f32; out_len + window];
+    let mut weight = vec![0f32; out_len + window];
+
+    let mut prev = 0;
+    for out_pos in (0..out_len).step_by(hop) {
+        let pos = if out_pos == 0 {
+            0
+        } else {
+            let nominal = (out_pos as f64 * speed as f64).round() as usize;
+            // the input that followed the previous frame, which the overlap
+            // of the new frame should look like
+            let target = &input[prev + hop..prev + 2 * hop];
+            best_offset(&input, target, nominal, tolerance)
+        };
+        for i in 0..window {
+            output[out_pos + i] += input[pos + i] * hann[i];
+            weight[out_pos + i] += hann[i];
+        }
+        prev = pos;
+    }
+
+    output.truncate(out_len);
+    for (sample, weight) in output.iter_mut().zip(weight) {
+        if weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+    output
+}
+
+/// Position in `nominal ± tolerance` whose samples correlate best with
+/// `target`.
+fn best_offset(input: &[f32], target: &[f32], nominal: usize, tolerance: usize) -> usize {
+    let low = nominal.saturating_sub(tolerance);
+    // the whole window, twice the overlap, must fit
+    let high = (nominal + tolerance).min(input.len() - 2 * target.len());
+    let score = |pos: usize| {
+        let candidate = &input[pos..pos + target.len()];
+        let (dot, energy) = candidate
+            .iter()
+            .zip(target)
+            .fold((0f32, 0f32), |(dot, energy), (c, t)| {
+                (dot + c * t, energy + c * c)
+            });
+        dot / (energy + 1e-9).sqrt()
+    };
+    let best_in = |positions: &mut dyn Iterator<Item = usize>| {
+        positions
+            .map(|pos| (pos, score(pos)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(low, |(pos, _)| pos)
+    };
+
+    let coarse = best_in(&mut (low..=high).step_by(COARSE_STEP));
+    let fine_low = coarse.saturating_sub(COARSE_STEP - 1).max(low);
+    let fine_high = (coarse + COARSE_STEP - 1).min(high);
+    best_in(&mut (fine_low..=fine_high))
+}
+
+#[test]
+fn test_time_stretch() {
+    let sample_rate = 16000;
+    let freq = 220.0;
+    let samples: Vec<f32> = (0..sample_rate)
+        .map(|i| (2.0 * PI * freq * i as f32 / sample_rate as f32).sin() * 0.5)
+        .collect();
+    // rising zero crossings per second, i.e. the frequency
+    let pitch = |samples: &[f32]| {
+        let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0);
+        crossings.count() as f32 * sample_rate as f32 / samples.len() as f32
+    };
+
+    assert_eq!(time_stretch(&samples, sample_rate, 1.0), samples);
+    for speed in [0.5, 0.8, 1.25, 2.0] {
+        let stretched = time_stretch(&samples, sample_rate, speed);
+        let expected = (samples.len() as f32 / speed).round() as usize;
+        assert_eq!(stretched.len(), expected);
+        assert!(
+            (pitch(&stretched) - freq).abs() < freq * 0.03,
+            "speed {}",
+            speed
+        );
+        // no clicks: a 220 Hz sine never jumps more than this between samples
+        let max_step = 2.0 * PI * freq / sample_rate as f32 * 0.5 * 1.2;
+        let window = sample_rate as usize * WINDOW_MS / 1000;
+        assert!(stretched[window..stretched.len() - window]
+            .windows(2)
+            .all(|w| (w[1] - w[0]).abs() < max_step));
+    }
+}
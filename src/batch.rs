@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use tch::Tensor;
+
+use crate::SamplingInput;
+
+/// TorchScript method a model exports to run several texts against one
+/// prompt. It takes the `forward` inputs with `text_phone_seq` padded to
+/// `[batch, max_len]`, `text_phone_lens: [batch]` after it and `bert_seq`
+/// padded to `[batch, max_len, 1024]`, and returns `(audio, audio_lens)` with
+/// `audio: [batch, max_samples]`.
+pub(crate) const BATCH_METHOD: &str = "infer_batch";
+
+/// Whether the argument names of `BATCH_METHOD` match the inputs passed to
+/// it: the `forward` inputs, `text_phone_lens` after the text phones and the
+/// sampling inputs detected for `forward`, in the same order.
+pub(crate) fn accepts(names: &[String], sampling_inputs: &[SamplingInput]) -> bool {
+    names.len() == crate::FORWARD_BASE_INPUTS + 1 + sampling_inputs.len()
+        && names[4] == "text_phone_lens"
+        && names[crate::FORWARD_BASE_INPUTS + 1..]
+            .iter()
+            .zip(sampling_inputs)
+            .all(|(name, input)| name == input.name())
+}
+
+/// Requests that can share one forward pass: same prompt and sampling params.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BatchKey {
+    pub prompt: usize,
+    pub top_k: i64,
+    pub top_p: u32,
+    pub temperature: u32,
+    pub speed: u32,
+}
+
+/// Phones and BERT features of one text, as returned by
+/// `text::get_phone_and_bert`.
+#[derive(Debug)]
+pub(crate) struct BatchInput {
+    pub phone_seq: Tensor,
+    pub bert_seq: Tensor,
+}
+
+#[derive(Debug)]
+struct Pending {
+    input: BatchInput,
+    result: mpsc::Sender<anyhow::Result<Tensor>>,
+}
+
+#[derive(Debug, Default)]
+struct Queues {
+    next_id: u64,
+    /// batches still accepting requests
+    open: HashMap<BatchKey, (u64, Vec<Pending>)>,
+    /// batches that reached the size limit, waiting for their leader
+    full: HashMap<u64, Vec<Pending>>,
+}
+
+/// Collects concurrent requests for the same prompt into one forward pass.
+///
+/// There is no extra thread: the first request of a batch leads it, waits up
+/// to `max_wait` for others to join and runs the batch for everyone. Callers
+/// must run on blocking threads.
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    max_batch_size: usize,
+    max_wait: Duration,
+    queues: Mutex<Queues>,
+    filled: Condvar,
+}
+
+impl Batcher {
+    pub(crate) fn new(max_batch_size: usize, max_wait: Duration) -> Self {
+        Self {
+            max_batch_size,
+            max_wait,
+            queues: Mutex::new(Queues::default()),
+            filled: Condvar::new(),
+        }
+    }
+
+    /// Runs `input` in a batch, `run` gets the inputs of the whole batch and
+    /// returns one output per input, in order.
+    pub(crate) fn infer(
+        &self,
+        key: BatchKey,
+        input: BatchInput,
+        run: impl FnOnce(&[BatchInput]) -> anyhow::Result<Vec<Tensor>>,
+    ) -> anyhow::Result<Tensor> {
+        let (tx, rx) = mpsc::channel();
+        let pending = Pending { input, result: tx };
+
+        let mut queues = self.queues.lock().unwrap();
+        let id = match queues.open.get_mut(&key) {
+            Some((_, batch)) => {
+                batch.push(pending);
+                if batch.len() >= self.max_batch_size {
+                    let (id, batch) = queues.open.remove(&key).unwrap();
+                    queues.full.insert(id, batch);
+                    self.filled.notify_all();
+                }
+                drop(queues);
+                return rx.recv()?;
+            }
+            None => {
+                let id = queues.next_id;
+                queues.next_id += 1;
+                queues.open.insert(key.clone(), (id, vec![pending]));
+                id
+            }
+        };
+
+        let deadline = Instant::now() + self.max_wait;
+        let batch = loop {
+            if let Some(batch) = queues.full.remove(&id) {
+                break batch;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                // not full, so the open batch of this key is still ours
+                break queues.open.remove(&key).unwrap().1;
+            }
+            queues = self.filled.wait_timeout(queues, deadline - now).unwrap().0;
+        };
+        drop(queues);
+
+        log::debug!("run batch of {}", batch.len());
+        let (inputs, results): (Vec<BatchInput>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.input, pending.result))
+            .unzip();
+        match run(&inputs) {
+            Ok(outputs) => {
+                for (result, output) in results.iter().zip(outputs) {
+                    let _ = result.send(Ok(output));
+                }
+            }
+            Err(e) => {
+                for result in &results {
+                    let _ = result.send(Err(anyhow::anyhow!("batch inference failed: {}", e)));
+                }
+            }
+        }
+        rx.recv()?
+    }
+}
+
+/// Pads the inputs of a batch to the longest text, returns the phones, their
+/// lengths and the BERT features.
+pub(crate) fn pad_batch(inputs: &[BatchInput]) -> (Tensor, Tensor, Tensor) {
+    let lens: Vec<i64> = inputs
+        .iter()
+        .map(|input| input.phone_seq.size()[1])
+        .collect();
+    let max_len = lens.iter().copied().max().unwrap_or(0);
+
+    let phone_seqs: Vec<Tensor> = inputs
+        .iter()
+        .zip(&lens)
+        .map(|(input, len)| input.phone_seq.constant_pad_nd([0, max_len - len]))
+        .collect();
+    let bert_seqs: Vec<Tensor> = inputs
+        .iter()
+        .zip(&lens)
+        .map(|(input, len)| input.bert_seq.constant_pad_nd([0, 0, 0, max_len - len]))
+        .collect();
+
+    let device = inputs[0].phone_seq.device();
+    (
+        Tensor::cat(&phone_seqs, 0),
+        Tensor::from_slice(&lens).to_device(device),
+        Tensor::stack(&bert_seqs, 0),
+    )
+}
+
+/// Splits the padded batch output back into one audio tensor per input.
+pub(crate) fn split_batch(audio: &Tensor, audio_lens: &Tensor) -> anyhow::Result<Vec<Tensor>> {
+    let lens = Vec::<i64>::try_from(audio_lens)?;
+    Ok(lens
+        .iter()
+        .enumerate()
+        .map(|(i, len)| audio.get(i as i64).narrow(0, 0, *len))
+        .collect())
+}
+
+#[test]
+fn test_batcher_collects_concurrent_requests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let batcher = Arc::new(Batcher::new(3, Duration::from_secs(10)));
+    let batches = Arc::new(AtomicUsize::new(0));
+    let key = BatchKey {
+        prompt: 1,
+        top_k: 5,
+        top_p: 1f32.to_bits(),
+        temperature: 1f32.to_bits(),
+        speed: 1f32.to_bits(),
+    };
+
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let (batcher, batches, key) = (batcher.clone(), batches.clone(), key.clone());
+            std::thread::spawn(move || {
+                let input = BatchInput {
+                    phone_seq: Tensor::from_slice(&[i as i64]),
+                    bert_seq: Tensor::from_slice(&[0f32]),
+                };
+                batcher
+                    .infer(key, input, |inputs| {
+                        batches.fetch_add(1, Ordering::SeqCst);
+                        assert_eq!(inputs.len(), 3);
+                        Ok(inputs.iter().map(|i| i.phone_seq.shallow_clone()).collect())
+                    })
+                    .unwrap()
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // the batch filled up long before the 10s wait
+    assert_eq!(batches.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_accepts() {
+    let names = |names: &str| -> Vec<String> { names.split(' ').map(String::from).collect() };
+    let batch = "ssl_content ref_audio_32k ref_phone_seq text_phone_seq text_phone_lens \
+        ref_bert_seq bert_seq top_k temperature";
+    let sampling = [SamplingInput::TopK, SamplingInput::Temperature];
+    assert!(accepts(&names(batch), &sampling));
+    // sampling inputs differ from forward
+    assert!(!accepts(&names(batch), &sampling[..1]));
+    assert!(!accepts(
+        &names(batch),
+        &[SamplingInput::TopK, SamplingInput::TopP]
+    ));
+    assert!(!accepts(
+        &names(batch),
+        &[SamplingInput::Temperature, SamplingInput::TopK]
+    ));
+    // lengths missing
+    let forward = "ssl_content ref_audio_32k ref_phone_seq text_phone_seq ref_bert_seq \
+        bert_seq top_k temperature";
+    assert!(!accepts(&names(forward), &sampling[..1]));
+}
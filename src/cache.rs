@@ -0,0 +1,981 @@
+// 合成结果缓存：磁盘上每个结果一个 WAV 文件，旁边的同名 JSON 文件记录缓存键，
+// 按总大小和条目数做 LRU 淘汰，访问频繁的结果另外保存在内存中
+// 除整个请求的结果外，还缓存单个分段（句子）的推理结果，长文本中相同的句子不再重复推理
+// CacheManager 只是索引，锁内不读写文件；文件读写由 load、save 等函数在锁外完成，
+// 这些函数会阻塞，异步代码中通过 web::block 或 spawn_blocking 调用
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gpt_sovits_rs::audio::{AudioFormat, SAMPLE_RATE};
+use gpt_sovits_rs::synthesizer::{ChunkCache, SynthesisOptions};
+use gpt_sovits_rs::InferParams;
+use hex::encode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+// 缓存键的格式版本，字段或其含义变化时加一，旧版本的缓存文件在清理时直接删除
+const CACHE_KEY_VERSION: u32 = 2;
+
+// 命中这么多次的结果才放入内存
+const MEMORY_MIN_HITS: u64 = 2;
+
+// 不在索引中的文件超过这个时间才删除，避免删掉刚写入、还没登记的结果
+const UNINDEXED_GRACE: Duration = Duration::from_secs(60);
+
+// 缓存键：所有影响输出音频的参数，按字段顺序序列化为 JSON 后取 sha256
+// 不含输出格式，缓存统一存为 32 位浮点 WAV，返回时再转码
+// 分段的缓存键没有 options，分段与停顿设置只影响拼接，不影响单个分段的推理结果
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheKey {
+    version: u32,
+    // 模型文件与参考音频、参考文本的哈希，替换模型或参考音频后旧缓存不再命中
+    fingerprint: String,
+    speaker: String,
+    emotion: String,
+    text: String,
+    params: InferParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<SynthesisOptions>,
+}
+
+impl CacheKey {
+    pub fn new(
+        fingerprint: String,
+        speaker: &str,
+        emotion: &str,
+        text: &str,
+        params: InferParams,
+        options: SynthesisOptions,
+    ) -> Self {
+        CacheKey {
+            version: CACHE_KEY_VERSION,
+            fingerprint,
+            speaker: speaker.to_string(),
+            emotion: emotion.to_string(),
+            text: text.to_string(),
+            params,
+            options: Some(options),
+        }
+    }
+
+    // 单个分段的缓存键，text 为分段的文本
+    pub fn chunk(
+        fingerprint: String,
+        speaker: &str,
+        emotion: &str,
+        chunk: &str,
+        params: InferParams,
+    ) -> Self {
+        CacheKey {
+            version: CACHE_KEY_VERSION,
+            fingerprint,
+            speaker: speaker.to_string(),
+            emotion: emotion.to_string(),
+            text: chunk.to_string(),
+            params,
+            options: None,
+        }
+    }
+
+    fn is_chunk(&self) -> bool {
+        self.options.is_none()
+    }
+
+    fn to_meta(&self, seed: Option<u64>) -> CacheMeta {
+        CacheMeta {
+            speaker: self.speaker.clone(),
+            emotion: self.emotion.clone(),
+            text: self.text.clone(),
+            seed,
+            options: self.options.as_ref().map(|_| serde::de::IgnoredAny),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("cache key is serializable")
+    }
+}
+
+// 从 JSON 文件中读回的缓存键，按声音和文本清除缓存时使用
+// 另外记录合成时实际使用的 seed，未指定 seed 的请求命中时通过 X-Seed 返回
+#[derive(Debug, Deserialize)]
+struct CacheMeta {
+    speaker: String,
+    emotion: String,
+    text: String,
+    #[serde(default)]
+    seed: Option<u64>,
+    // 只用来区分整个请求的结果和分段的结果
+    #[serde(default)]
+    options: Option<serde::de::IgnoredAny>,
+}
+
+impl CacheMeta {
+    fn is_chunk(&self) -> bool {
+        self.options.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    // 磁盘缓存的总大小和条目数上限，超过时淘汰最久未使用的结果
+    pub max_bytes: u64,
+    pub max_entries: usize,
+    // 结果写入后的有效期
+    pub ttl: Duration,
+    // 内存缓存的大小上限，0 表示不使用内存缓存
+    pub memory_max_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_bytes: 1024 * 1024 * 1024,
+            max_entries: 10000,
+            ttl: Duration::from_secs(86400),
+            memory_max_bytes: 0,
+        }
+    }
+}
+
+struct Entry {
+    bytes: u64,
+    created: SystemTime,
+    // 最近一次访问的序号，越大越新
+    last_used: u64,
+    hits: u64,
+    // 缺少 JSON 文件时为 None，这样的结果只能随全部清除或淘汰删除
+    meta: Option<CacheMeta>,
+}
+
+// 内存中的结果，同样按最近访问淘汰
+struct MemoryEntry {
+    samples: Arc<Vec<f32>>,
+    last_used: u64,
+}
+
+pub struct CacheManager {
+    cache_dir: String,
+    config: CacheConfig,
+    entries: HashMap<String, Entry>,
+    // 访问序号到文件名，第一个即最久未使用的条目
+    lru: BTreeMap<u64, String>,
+    total_bytes: u64,
+    memory: HashMap<String, MemoryEntry>,
+    memory_lru: BTreeMap<u64, String>,
+    memory_bytes: u64,
+    clock: u64,
+    // 启动以来的命中与未命中次数，整个请求与分段分开统计
+    hits: u64,
+    misses: u64,
+    chunk_hits: u64,
+    chunk_misses: u64,
+}
+
+impl CacheManager {
+    pub fn new(cache_dir: &str, config: CacheConfig) -> Self {
+        // 确保缓存目录存在
+        if !Path::new(cache_dir).exists() {
+            fs::create_dir_all(cache_dir).unwrap_or_else(|e| {
+                log::warn!("无法创建缓存目录 {}: {}", cache_dir, e);
+            });
+        }
+
+        let mut cache = CacheManager {
+            cache_dir: cache_dir.to_string(),
+            config,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            total_bytes: 0,
+            memory: HashMap::new(),
+            memory_lru: BTreeMap::new(),
+            memory_bytes: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            chunk_hits: 0,
+            chunk_misses: 0,
+        };
+        // 启动时还没有其他线程使用，直接在这里删除文件
+        cache.scan();
+        for filename in cache.evict() {
+            remove_files(Path::new(&filename));
+        }
+        cache
+    }
+
+    // 启动时按修改时间从旧到新登记已有的缓存文件，旧版本与过期的文件直接删除
+    fn scan(&mut self) {
+        let entries = match fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("无法读取缓存目录 {}: {}", self.cache_dir, e);
+                return;
+            }
+        };
+
+        let mut files = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            // 上次退出时没写完的临时文件
+            if metadata.is_file() && path.extension().is_some_and(|ext| ext == "tmp") {
+                remove_file(&path);
+                continue;
+            }
+            if !metadata.is_file() || !is_wav(&path) {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            if self.is_stale(&path, modified) {
+                remove_files(&path);
+                continue;
+            }
+            files.push((modified, path.to_string_lossy().to_string(), metadata.len()));
+        }
+
+        files.sort();
+        for (created, filename, bytes) in files {
+            let meta = read_meta(&filename);
+            self.insert_entry(filename, bytes, created, meta);
+        }
+        log::info!(
+            "缓存目录 {} 中有 {} 个结果，共 {} 字节",
+            self.cache_dir,
+            self.entries.len(),
+            self.total_bytes
+        );
+    }
+
+    // 旧版本缓存键的文件不会再命中，超过有效期的文件也不再使用
+    fn is_stale(&self, path: &Path, created: SystemTime) -> bool {
+        let name = path.file_name().and_then(|name| name.to_str());
+        let old_version = !name.unwrap_or_default().starts_with(&cache_file_prefix());
+        let expired = SystemTime::now()
+            .duration_since(created)
+            .is_ok_and(|age| age > self.config.ttl);
+        old_version || expired
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert_entry(
+        &mut self,
+        filename: String,
+        bytes: u64,
+        created: SystemTime,
+        meta: Option<CacheMeta>,
+    ) {
+        self.remove_entry(&filename);
+        let last_used = self.tick();
+        self.lru.insert(last_used, filename.clone());
+        self.total_bytes += bytes;
+        self.entries.insert(
+            filename,
+            Entry {
+                bytes,
+                created,
+                last_used,
+                hits: 0,
+                meta,
+            },
+        );
+    }
+
+    // 只从索引和内存中移除，不删除文件
+    fn remove_entry(&mut self, filename: &str) -> bool {
+        self.remove_from_memory(filename);
+        match self.entries.remove(filename) {
+            Some(entry) => {
+                self.lru.remove(&entry.last_used);
+                self.total_bytes -= entry.bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_from_memory(&mut self, filename: &str) {
+        if let Some(entry) = self.memory.remove(filename) {
+            self.memory_lru.remove(&entry.last_used);
+            self.memory_bytes -= samples_bytes(&entry.samples);
+        }
+    }
+
+    // 生成缓存文件名，文件名带上缓存键版本
+    fn get_cache_filename(&self, key_json: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(key_json.as_bytes());
+        let hash = encode(hasher.finalize());
+
+        let name = format!("{}{}.wav", cache_file_prefix(), hash);
+        // 与扫描目录时得到的路径一致
+        Path::new(&self.cache_dir)
+            .join(name)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // 查找缓存并更新访问顺序和命中统计，内存中没有时返回需要在锁外读取的文件
+    fn lookup(&mut self, key: &CacheKey) -> Lookup {
+        let filename = self.get_cache_filename(&key.to_json());
+        let lookup = self.lookup_file(filename);
+        let (hits, misses) = if key.is_chunk() {
+            (&mut self.chunk_hits, &mut self.chunk_misses)
+        } else {
+            (&mut self.hits, &mut self.misses)
+        };
+        match lookup {
+            Lookup::Miss { .. } => *misses += 1,
+            _ => *hits += 1,
+        }
+        lookup
+    }
+
+    fn lookup_file(&mut self, filename: String) -> Lookup {
+        let Some(entry) = self.entries.get(&filename) else {
+            log::debug!("缓存未命中: {}", filename);
+            return Lookup::Miss { stale: None };
+        };
+        if self.is_stale(Path::new(&filename), entry.created) {
+            log::debug!("缓存已过期: {}", filename);
+            self.remove_entry(&filename);
+            return Lookup::Miss {
+                stale: Some(filename),
+            };
+        }
+
+        let last_used = self.tick();
+        let entry = self.entries.get_mut(&filename).unwrap();
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(last_used, filename.clone());
+        entry.last_used = last_used;
+        entry.hits += 1;
+        let seed = entry.meta.as_ref().and_then(|meta| meta.seed);
+        let promote = entry.hits >= MEMORY_MIN_HITS;
+
+        if let Some(memory_entry) = self.memory.get_mut(&filename) {
+            self.memory_lru.remove(&memory_entry.last_used);
+            self.memory_lru.insert(last_used, filename.clone());
+            memory_entry.last_used = last_used;
+            log::debug!("从内存缓存加载音频: {}", filename);
+            return Lookup::Memory(memory_entry.samples.clone(), seed);
+        }
+        Lookup::Disk {
+            filename,
+            seed,
+            promote,
+        }
+    }
+
+    // 文件读出后放入内存，读取期间条目已被淘汰或清除的不再放入
+    fn loaded(&mut self, filename: &str, samples: Arc<Vec<f32>>) {
+        if self.memory.contains_key(filename) {
+            return;
+        }
+        if let Some(entry) = self.entries.get(filename) {
+            let last_used = entry.last_used;
+            self.insert_into_memory(filename, samples, last_used);
+        }
+    }
+
+    // 文件读取失败，从索引中移除，这次访问改记为未命中
+    fn read_failed(&mut self, key: &CacheKey, filename: &str) {
+        self.remove_entry(filename);
+        if key.is_chunk() {
+            self.chunk_hits -= 1;
+            self.chunk_misses += 1;
+        } else {
+            self.hits -= 1;
+            self.misses += 1;
+        }
+    }
+
+    // 结果是否在缓存中，不更新访问顺序和命中统计，缓存预热时使用
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        let filename = self.get_cache_filename(&key.to_json());
+        self.entries
+            .get(&filename)
+            .is_some_and(|entry| !self.is_stale(Path::new(&filename), entry.created))
+    }
+
+    fn insert_into_memory(&mut self, filename: &str, samples: Arc<Vec<f32>>, last_used: u64) {
+        let bytes = samples_bytes(&samples);
+        if bytes > self.config.memory_max_bytes {
+            return;
+        }
+        self.memory_bytes += bytes;
+        self.memory_lru.insert(last_used, filename.to_string());
+        self.memory
+            .insert(filename.to_string(), MemoryEntry { samples, last_used });
+
+        while self.memory_bytes > self.config.memory_max_bytes {
+            let Some((_, oldest)) = self.memory_lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.memory.remove(&oldest) {
+                self.memory_bytes -= samples_bytes(&entry.samples);
+            }
+        }
+    }
+
+    // 登记已写入的结果，返回被淘汰、需要删除的文件
+    fn insert(&mut self, filename: String, bytes: u64, meta: CacheMeta) -> Vec<String> {
+        self.insert_entry(filename, bytes, SystemTime::now(), Some(meta));
+        self.evict()
+    }
+
+    // 超过大小或条目数上限时，从最久未使用的结果开始移出索引
+    fn evict(&mut self) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.total_bytes > self.config.max_bytes
+            || self.entries.len() > self.config.max_entries
+        {
+            let Some((_, oldest)) = self.lru.first_key_value() else {
+                break;
+            };
+            let oldest = oldest.clone();
+            log::debug!("淘汰缓存: {}", oldest);
+            self.remove_entry(&oldest);
+            evicted.push(oldest);
+        }
+        evicted
+    }
+
+    // 移出满足条件的结果，返回需要删除的文件
+    fn purge(&mut self, matches: impl Fn(&Entry) -> bool) -> Vec<String> {
+        let filenames: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| matches(entry))
+            .map(|(filename, _)| filename.clone())
+            .collect();
+        for filename in &filenames {
+            self.remove_entry(filename);
+        }
+        filenames
+    }
+
+    pub fn stats(&self) -> Value {
+        let oldest = self.entries.values().map(|entry| entry.created).min();
+        let unix_secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        };
+        let ratio = |hits: u64, misses: u64| {
+            if hits + misses > 0 {
+                hits as f64 / (hits + misses) as f64
+            } else {
+                0.0
+            }
+        };
+        let chunk_entries = self
+            .entries
+            .values()
+            .filter(|entry| entry.meta.as_ref().is_some_and(CacheMeta::is_chunk))
+            .count();
+        json!({
+            "entries": self.entries.len(),
+            "chunk_entries": chunk_entries,
+            "bytes": self.total_bytes,
+            "max_entries": self.config.max_entries,
+            "max_bytes": self.config.max_bytes,
+            "ttl_secs": self.config.ttl.as_secs(),
+            "memory_entries": self.memory.len(),
+            "memory_bytes": self.memory_bytes,
+            "memory_max_bytes": self.config.memory_max_bytes,
+            "hits": self.hits,
+            "misses": self.misses,
+            "hit_ratio": ratio(self.hits, self.misses),
+            "chunk_hits": self.chunk_hits,
+            "chunk_misses": self.chunk_misses,
+            "chunk_hit_ratio": ratio(self.chunk_hits, self.chunk_misses),
+            "oldest_entry": oldest.map(unix_secs),
+            "oldest_entry_age_secs": oldest.map(|created| {
+                SystemTime::now().duration_since(created).map(|d| d.as_secs()).unwrap_or(0)
+            }),
+        })
+    }
+}
+
+enum Lookup {
+    Memory(Arc<Vec<f32>>, Option<u64>),
+    // 需要在锁外读取文件，promote 表示读出后放入内存
+    Disk {
+        filename: String,
+        seed: Option<u64>,
+        promote: bool,
+    },
+    // stale 为已过期、需要删除的文件
+    Miss {
+        stale: Option<String>,
+    },
+}
+
+// 要清除的缓存
+pub enum Purge<'a> {
+    All,
+    Speaker(&'a str),
+    // 某个声音某段文本的结果，不限参数；未指定 emotion 时包括所有情感
+    Text {
+        speaker: &'a str,
+        text: &'a str,
+        emotion: Option<&'a str>,
+    },
+}
+
+fn lock(cache: &Mutex<CacheManager>) -> Option<MutexGuard<'_, CacheManager>> {
+    match cache.lock() {
+        Ok(guard) => Some(guard),
+        Err(e) => {
+            log::error!("获取缓存锁失败: {}", e);
+            None
+        }
+    }
+}
+
+// 从缓存加载音频和合成时使用的 seed，先查内存再查磁盘
+pub fn load(cache: &Mutex<CacheManager>, key: &CacheKey) -> Option<(Arc<Vec<f32>>, Option<u64>)> {
+    let lookup = lock(cache)?.lookup(key);
+    match lookup {
+        Lookup::Memory(samples, seed) => Some((samples, seed)),
+        Lookup::Disk {
+            filename,
+            seed,
+            promote,
+        } => match read_wav(&filename) {
+            Ok(samples) => {
+                log::info!("从缓存加载音频: {}", filename);
+                let samples = Arc::new(samples);
+                if promote {
+                    lock(cache)?.loaded(&filename, samples.clone());
+                }
+                Some((samples, seed))
+            }
+            Err(e) => {
+                log::warn!("读取缓存文件 {} 失败: {}", filename, e);
+                lock(cache)?.read_failed(key, &filename);
+                None
+            }
+        },
+        Lookup::Miss { stale } => {
+            if let Some(filename) = stale {
+                remove_files(Path::new(&filename));
+            }
+            None
+        }
+    }
+}
+
+// 保存音频到缓存，统一存为 32 位浮点 WAV，返回时再按请求的格式转码
+// 缓存键另存为同名 JSON 文件，重启后仍能按声音和文本清除
+pub fn save(cache: &Mutex<CacheManager>, key: &CacheKey, samples: &[f32], seed: Option<u64>) {
+    let Some(filename) = lock(cache).map(|cache| cache.get_cache_filename(&key.to_json())) else {
+        return;
+    };
+    let mut meta_json = serde_json::to_value(key).expect("cache key is serializable");
+    meta_json["seed"] = json!(seed);
+    let wav_data = match AudioFormat::WavF32.encode(samples, SAMPLE_RATE) {
+        Ok(wav_data) => wav_data,
+        Err(e) => {
+            log::warn!("编码缓存音频失败: {}", e);
+            return;
+        }
+    };
+    // 先写临时文件再改名，同时读取这个结果的请求不会读到写了一半的文件
+    if let Err(e) = write_atomic(Path::new(&filename), &wav_data) {
+        log::warn!("写入缓存文件失败: {}", e);
+        return;
+    }
+    if let Err(e) = fs::write(meta_path(Path::new(&filename)), meta_json.to_string()) {
+        log::warn!("写入缓存键文件失败: {}", e);
+    }
+    log::info!("已保存音频到缓存: {}", filename);
+
+    let Some(mut guard) = lock(cache) else {
+        return;
+    };
+    let evicted = guard.insert(filename, wav_data.len() as u64, key.to_meta(seed));
+    drop(guard);
+    for filename in evicted {
+        remove_files(Path::new(&filename));
+    }
+}
+
+// 清除缓存，返回清除的结果数；清除全部时也删除不在索引中的文件
+pub fn purge(cache: &Mutex<CacheManager>, target: Purge) -> usize {
+    let Some(mut guard) = lock(cache) else {
+        return 0;
+    };
+    let purged = match target {
+        Purge::All => guard.purge(|_| true),
+        Purge::Speaker(speaker) => guard.purge(|entry| {
+            entry
+                .meta
+                .as_ref()
+                .is_some_and(|meta| meta.speaker == speaker)
+        }),
+        Purge::Text {
+            speaker,
+            text,
+            emotion,
+        } => guard.purge(|entry| {
+            entry.meta.as_ref().is_some_and(|meta| {
+                meta.speaker == speaker
+                    && meta.text == text
+                    && emotion.is_none_or(|emotion| meta.emotion == emotion)
+            })
+        }),
+    };
+    drop(guard);
+
+    for filename in &purged {
+        remove_files(Path::new(filename));
+    }
+    match target {
+        Purge::All => {
+            remove_unindexed(cache);
+            log::info!("已清除全部缓存，共 {} 个结果", purged.len());
+        }
+        Purge::Speaker(speaker) => {
+            log::info!("已清除声音 {} 的缓存，共 {} 个结果", speaker, purged.len());
+        }
+        Purge::Text { .. } => {}
+    }
+    purged.len()
+}
+
+// 清理过期的结果，以及目录中不在索引里的旧版本文件
+pub fn cleanup(cache: &Mutex<CacheManager>) {
+    let Some(mut guard) = lock(cache) else {
+        return;
+    };
+    log::info!("开始清理缓存目录: {}", guard.cache_dir);
+    let now = SystemTime::now();
+    let ttl = guard.config.ttl;
+    let expired = guard.purge(|entry| now.duration_since(entry.created).is_ok_and(|age| age > ttl));
+    drop(guard);
+
+    for filename in &expired {
+        remove_files(Path::new(filename));
+    }
+    remove_unindexed(cache);
+
+    if let Some(guard) = lock(cache) {
+        log::info!(
+            "缓存清理完成，删除 {} 个过期结果，剩余 {} 个，共 {} 字节",
+            expired.len(),
+            guard.entries.len(),
+            guard.total_bytes
+        );
+    }
+}
+
+// 删除目录中不在索引里的 WAV 文件，以及没有对应 WAV 的 JSON 文件
+// 目录在锁外读取，只在核对索引时加锁
+fn remove_unindexed(cache: &Mutex<CacheManager>) {
+    let Some(cache_dir) = lock(cache).map(|cache| cache.cache_dir.clone()) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(&cache_dir) else {
+        log::warn!("无法读取缓存目录: {}", cache_dir);
+        return;
+    };
+    let now = SystemTime::now();
+    let mut candidates = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let recent = metadata.modified().is_ok_and(|modified| {
+            now.duration_since(modified).unwrap_or_default() < UNINDEXED_GRACE
+        });
+        if !metadata.is_file() || recent {
+            continue;
+        }
+        let wav = if is_wav(&path) {
+            path.clone()
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            path.with_extension("wav")
+        } else {
+            continue;
+        };
+        candidates.push((path, wav.to_string_lossy().to_string()));
+    }
+
+    let unindexed: Vec<PathBuf> = {
+        let Some(guard) = lock(cache) else {
+            return;
+        };
+        candidates
+            .into_iter()
+            .filter(|(_, wav)| !guard.entries.contains_key(wav))
+            .map(|(path, _)| path)
+            .collect()
+    };
+    for path in unindexed {
+        remove_file(&path);
+    }
+}
+
+// 供 infer_long_streaming_cached 使用的分段缓存，在推理线程中调用，只在更新索引时短暂持有锁
+pub struct CachedChunks {
+    cache: Arc<Mutex<CacheManager>>,
+    fingerprint: String,
+    speaker: String,
+    emotion: String,
+    params: InferParams,
+}
+
+impl CachedChunks {
+    pub fn new(
+        cache: Arc<Mutex<CacheManager>>,
+        fingerprint: String,
+        speaker: &str,
+        emotion: &str,
+        params: InferParams,
+    ) -> Self {
+        CachedChunks {
+            cache,
+            fingerprint,
+            speaker: speaker.to_string(),
+            emotion: emotion.to_string(),
+            params,
+        }
+    }
+
+    fn key(&self, chunk: &str) -> CacheKey {
+        CacheKey::chunk(
+            self.fingerprint.clone(),
+            &self.speaker,
+            &self.emotion,
+            chunk,
+            self.params,
+        )
+    }
+}
+
+impl ChunkCache for CachedChunks {
+    fn load(&mut self, chunk: &str) -> Option<Vec<f32>> {
+        let key = self.key(chunk);
+        load(&self.cache, &key).map(|(samples, _)| samples.to_vec())
+    }
+
+    fn save(&mut self, chunk: &str, samples: &[f32]) {
+        let key = self.key(chunk);
+        save(&self.cache, &key, samples, self.params.seed);
+    }
+}
+
+fn cache_file_prefix() -> String {
+    format!("v{}_", CACHE_KEY_VERSION)
+}
+
+fn is_wav(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "wav")
+}
+
+fn meta_path(wav: &Path) -> PathBuf {
+    wav.with_extension("json")
+}
+
+fn read_meta(filename: &str) -> Option<CacheMeta> {
+    let data = fs::read(meta_path(Path::new(filename))).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(meta) => Some(meta),
+        Err(e) => {
+            log::warn!("缓存键文件 {} 无法解析: {}", filename, e);
+            None
+        }
+    }
+}
+
+fn samples_bytes(samples: &[f32]) -> u64 {
+    std::mem::size_of_val(samples) as u64
+}
+
+// 写入同目录下的临时文件后改名，临时文件名各不相同，同一结果的并发写入互不影响
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT_TMP.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("{}.{}.tmp", std::process::id(), n));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+fn read_wav(filename: &str) -> Result<Vec<f32>, String> {
+    let file = fs::File::open(filename).map_err(|e| e.to_string())?;
+    let (_, samples) = wav_io::read_from_file(file).map_err(|e| e.to_string())?;
+    Ok(samples)
+}
+
+// 删除结果的 WAV 文件和 JSON 文件
+fn remove_files(wav: &Path) {
+    remove_file(wav);
+    let meta = meta_path(wav);
+    if meta.exists() {
+        remove_file(&meta);
+    }
+}
+
+fn remove_file(path: &Path) {
+    log::info!("删除缓存文件: {}", path.display());
+    if let Err(e) = fs::remove_file(path) {
+        log::warn!("无法删除缓存文件 {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+fn test_cache(name: &str, config: CacheConfig) -> (PathBuf, Mutex<CacheManager>) {
+    let dir =
+        std::env::temp_dir().join(format!("gpt_sovits_cache_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cache = CacheManager::new(dir.to_str().unwrap(), config);
+    (dir, Mutex::new(cache))
+}
+
+#[cfg(test)]
+fn test_key(text: &str) -> CacheKey {
+    CacheKey::new(
+        "fingerprint".to_string(),
+        "alice",
+        "default",
+        text,
+        InferParams::default(),
+        SynthesisOptions::default(),
+    )
+}
+
+#[test]
+fn test_cache_lru_eviction() {
+    // 按条目数淘汰最久未使用的结果，读取会更新访问顺序
+    let config = CacheConfig {
+        max_entries: 2,
+        ..Default::default()
+    };
+    let (dir, cache) = test_cache("lru", config);
+    let samples = vec![0.5; 100];
+    save(&cache, &test_key("a"), &samples, None);
+    save(&cache, &test_key("b"), &samples, None);
+    assert!(load(&cache, &test_key("a")).is_some());
+    save(&cache, &test_key("c"), &samples, None);
+    assert!(load(&cache, &test_key("b")).is_none());
+    assert!(load(&cache, &test_key("a")).is_some());
+    assert!(load(&cache, &test_key("c")).is_some());
+    // 淘汰的结果连同 JSON 文件一起删除
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+    // 按总大小淘汰
+    let wav_bytes = AudioFormat::WavF32
+        .encode(&samples, SAMPLE_RATE)
+        .unwrap()
+        .len() as u64;
+    let config = CacheConfig {
+        max_bytes: wav_bytes * 2,
+        ..Default::default()
+    };
+    let (dir, cache) = test_cache("bytes", config);
+    for text in ["a", "b", "c"] {
+        save(&cache, &test_key(text), &samples, None);
+    }
+    assert!(load(&cache, &test_key("a")).is_none());
+    assert!(load(&cache, &test_key("b")).is_some());
+    let stats = cache.lock().unwrap().stats();
+    assert_eq!(stats["entries"], 2);
+    assert_eq!(stats["bytes"], wav_bytes * 2);
+    assert_eq!(stats["hits"], 1);
+    assert_eq!(stats["misses"], 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cache_ttl() {
+    let config = CacheConfig {
+        ttl: Duration::ZERO,
+        ..Default::default()
+    };
+    let (dir, cache) = test_cache("ttl", config);
+    save(&cache, &test_key("a"), &[0.5; 100], None);
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(load(&cache, &test_key("a")).is_none());
+    assert_eq!(cache.lock().unwrap().stats()["entries"], 0);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cache_memory() {
+    let samples = vec![0.5; 100];
+    let config = CacheConfig {
+        memory_max_bytes: samples_bytes(&samples) * 3 / 2,
+        ..Default::default()
+    };
+    let (dir, cache) = test_cache("memory", config);
+    let filename = |text| {
+        cache
+            .lock()
+            .unwrap()
+            .get_cache_filename(&test_key(text).to_json())
+    };
+    save(&cache, &test_key("a"), &samples, Some(7));
+    save(&cache, &test_key("b"), &samples, None);
+
+    // 第二次命中后放入内存，之后不再读文件
+    assert_eq!(load(&cache, &test_key("a")).unwrap().1, Some(7));
+    assert_eq!(cache.lock().unwrap().stats()["memory_entries"], 0);
+    load(&cache, &test_key("a")).unwrap();
+    assert_eq!(cache.lock().unwrap().stats()["memory_entries"], 1);
+    fs::remove_file(filename("a")).unwrap();
+    let (memory_samples, seed) = load(&cache, &test_key("a")).unwrap();
+    assert_eq!(*memory_samples, samples);
+    assert_eq!(seed, Some(7));
+
+    // 内存只放得下一个结果，b 放入后淘汰 a
+    load(&cache, &test_key("b")).unwrap();
+    load(&cache, &test_key("b")).unwrap();
+    let stats = cache.lock().unwrap().stats();
+    assert_eq!(stats["memory_entries"], 1);
+    assert_eq!(stats["memory_bytes"], samples_bytes(&samples));
+    // a 的文件已删除，读取失败后移出索引
+    assert!(load(&cache, &test_key("a")).is_none());
+    assert_eq!(cache.lock().unwrap().stats()["entries"], 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cache_wav_round_trip() {
+    let (dir, cache) = test_cache("wav", CacheConfig::default());
+    let samples: Vec<f32> = (0..1000).map(|i| (i as f32 / 50.0).sin() * 0.8).collect();
+    save(&cache, &test_key("a"), &samples, None);
+
+    let filename = cache
+        .lock()
+        .unwrap()
+        .get_cache_filename(&test_key("a").to_json());
+    let (header, read) = wav_io::read_from_file(fs::File::open(&filename).unwrap()).unwrap();
+    assert_eq!(header.sample_rate, SAMPLE_RATE);
+    assert_eq!(read, samples);
+
+    // 重启后从目录中重新登记
+    drop(cache);
+    let cache = CacheManager::new(dir.to_str().unwrap(), CacheConfig::default());
+    assert_eq!(cache.stats()["entries"], 1);
+    let cache = Mutex::new(cache);
+    assert_eq!(*load(&cache, &test_key("a")).unwrap().0, samples);
+    assert_eq!(purge(&cache, Purge::Speaker("alice")), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
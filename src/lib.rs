@@ -1,10 +1,20 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}, usize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, RwLock, Weak},
+};
 
 use anyhow::Ok;
+use batch::{BatchInput, BatchKey, Batcher};
+use prompt_cache::PromptCache;
 use tch::{IValue, Tensor};
 use text::{g2pw::G2PWConverter, CNBertModel};
 
+pub mod audio;
+mod batch;
+mod prompt_cache;
 pub mod symbols;
+pub mod synthesizer;
 pub mod text;
 pub use tch::Device;
 pub mod voice_manager;
@@ -12,6 +22,12 @@ pub mod voice_manager;
 pub struct GPTSovitsConfig {
     pub cn_setting: Option<(String, String, String)>,
     pub ssl_path: String,
+    /// Directory to store precomputed speaker prompts in, see `with_prompt_cache`.
+    pub prompt_cache_dir: Option<String>,
+    /// Instances loaded per speaker model, see `with_model_instances`.
+    pub model_instances: usize,
+    /// Max batch size and max wait in milliseconds, see `with_batching`.
+    pub batching: Option<(usize, u64)>,
 }
 
 impl GPTSovitsConfig {
@@ -19,9 +35,20 @@ impl GPTSovitsConfig {
         Self {
             cn_setting: None,
             ssl_path,
+            prompt_cache_dir: None,
+            model_instances: 1,
+            batching: None,
         }
     }
 
+    /// Stores the SSL features, phones and BERT features of each reference
+    /// clip in `dir`, so loading the same clip again skips those models. If
+    /// the directory cannot be used the cache is disabled with a warning.
+    pub fn with_prompt_cache(mut self, dir: String) -> Self {
+        self.prompt_cache_dir = Some(dir);
+        self
+    }
+
     pub fn with_chinese(
         mut self,
         g2pw_path: String,
@@ -32,6 +59,21 @@ impl GPTSovitsConfig {
         self
     }
 
+    /// Loads each speaker model `instances` times so that many requests for
+    /// the same speaker run in parallel. Memory grows with every instance.
+    pub fn with_model_instances(mut self, instances: usize) -> Self {
+        self.model_instances = instances.max(1);
+        self
+    }
+
+    /// Runs concurrent requests for the same prompt as one batch, waiting up
+    /// to `max_wait_ms` for up to `max_batch_size` of them. Only models that
+    /// export a batch method are batched, see `batch::BATCH_METHOD`.
+    pub fn with_batching(mut self, max_batch_size: usize, max_wait_ms: u64) -> Self {
+        self.batching = (max_batch_size > 1).then_some((max_batch_size, max_wait_ms));
+        self
+    }
+
     pub fn build(&self, device: Device) -> anyhow::Result<GPTSovits> {
         let (cn_bert, g2pw) = match &self.cn_setting {
             Some((g2pw_path, cn_bert_path, tokenizer_path)) => {
@@ -53,6 +95,27 @@ impl GPTSovitsConfig {
         let mut ssl = tch::CModule::load_on_device(&self.ssl_path, device).unwrap();
         ssl.set_eval();
 
+        let prompt_cache = match &self.prompt_cache_dir {
+            Some(dir) => {
+                let mut model_paths = vec![self.ssl_path.as_str()];
+                if let Some((g2pw_path, cn_bert_path, tokenizer_path)) = &self.cn_setting {
+                    model_paths.extend([
+                        g2pw_path.as_str(),
+                        cn_bert_path.as_str(),
+                        tokenizer_path.as_str(),
+                    ]);
+                }
+                match PromptCache::new(dir, &model_paths) {
+                    Result::Ok(cache) => Some(cache),
+                    Err(e) => {
+                        log::warn!("prompt cache {} disabled: {}", dir, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
         Ok(GPTSovits {
             zh_bert: cn_bert,
             g2pw,
@@ -60,20 +123,360 @@ impl GPTSovitsConfig {
             symbols: symbols::SYMBOLS.clone(),
             ssl,
             jieba: jieba_rs::Jieba::new(),
-            speakers: HashMap::new(),
+            speakers: RwLock::new(HashMap::new()),
+            models: Mutex::new(HashMap::new()),
+            prompt_cache,
+            model_instances: self.model_instances,
+            batching: self.batching,
         })
     }
 }
 
+/// Sampling parameters for the GPT stage of the exported model.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct InferParams {
+    pub top_k: i64,
+    pub top_p: f32,
+    pub temperature: f32,
+    /// Speaking rate, 2.0 is twice as fast. Passed to the model if it takes a
+    /// `speed` input, otherwise the output is time-stretched.
+    pub speed: f32,
+    /// Seeds libtorch's generator before sampling, so the same text, speaker
+    /// and params produce the same audio.
+    pub seed: Option<u64>,
+}
+
+impl Default for InferParams {
+    fn default() -> Self {
+        Self {
+            top_k: 5,
+            top_p: 1.0,
+            temperature: 1.0,
+            speed: 1.0,
+            seed: None,
+        }
+    }
+}
+
+impl InferParams {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.top_k < 1 {
+            return Err(anyhow::anyhow!("top_k must be >= 1, got {}", self.top_k));
+        }
+        if !(self.top_p > 0.0 && self.top_p <= 1.0) {
+            return Err(anyhow::anyhow!(
+                "top_p must be in (0, 1], got {}",
+                self.top_p
+            ));
+        }
+        if !(self.temperature > 0.0 && self.temperature.is_finite()) {
+            return Err(anyhow::anyhow!(
+                "temperature must be > 0, got {}",
+                self.temperature
+            ));
+        }
+        if !(0.25..=4.0).contains(&self.speed) {
+            return Err(anyhow::anyhow!(
+                "speed must be in [0.25, 4.0], got {}",
+                self.speed
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Errors caused by the request rather than by the models, so callers can tell
+/// them apart with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InferError {
+    SpeakerNotFound(String),
+    PromptNotFound {
+        speaker: String,
+        prompt: String,
+    },
+    /// The text yields nothing to synthesize, or a number expression in it
+    /// cannot be parsed.
+    InvalidText(String),
+}
+
+impl std::fmt::Display for InferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InferError::SpeakerNotFound(speaker) => write!(f, "speaker not found: {}", speaker),
+            InferError::PromptNotFound { speaker, prompt } => {
+                write!(f, "prompt {} not found for speaker {}", prompt, speaker)
+            }
+            InferError::InvalidText(reason) => write!(f, "invalid text: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for InferError {}
+
+/// libtorch samples from one global generator. Seeded runs hold it exclusively
+/// so no other forward pass draws from it in between.
+static SAMPLING_RNG: RwLock<()> = RwLock::new(());
+
+/// The positional inputs every exported `forward` takes before the optional
+/// sampling inputs.
+const FORWARD_BASE_INPUTS: usize = 6;
+
+/// Reads the argument names of a method from the schema error TorchScript
+/// raises when it is called without inputs. No computation is run. `None` if
+/// the module has no such method.
+fn method_arg_names(module: &tch::CModule, method: &str) -> Option<Vec<String>> {
+    let err = module.method_is::<IValue>(method, &[]).err()?.to_string();
+    parse_declaration(&err, method)
+}
+
+/// Argument names in the `Declaration: method(...)` part of a schema error.
+fn parse_declaration(err: &str, method: &str) -> Option<Vec<String>> {
+    let decl = &err[err.find("Declaration: ")? + "Declaration: ".len()..];
+    let args = decl.strip_prefix(method)?.strip_prefix('(')?;
+    let args = &args[..args.find(')')?];
+
+    let names = args
+        .split(',')
+        .filter_map(|arg| {
+            let arg = arg.split('=').next()?.trim();
+            arg.rsplit(' ').next().map(|s| s.to_string())
+        })
+        .filter(|name| name != "self")
+        .collect();
+    Some(names)
+}
+
+/// Sampling inputs that the loaded `forward` accepts after the base inputs,
+/// in declaration order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SamplingInput {
+    TopK,
+    TopP,
+    Temperature,
+    Speed,
+}
+
+impl SamplingInput {
+    fn detect(module: &tch::CModule) -> Vec<Self> {
+        let names = match method_arg_names(module, "forward") {
+            Some(names) => names,
+            None => {
+                log::warn!("unable to read forward schema, assume it only accepts top_k");
+                return vec![SamplingInput::TopK];
+            }
+        };
+        log::debug!("forward inputs: {:?}", names);
+
+        let mut inputs = vec![];
+        for name in names.iter().skip(FORWARD_BASE_INPUTS) {
+            let input = match SamplingInput::ALL
+                .into_iter()
+                .find(|input| input.name() == name)
+            {
+                Some(input) => input,
+                None => {
+                    log::warn!(
+                        "unknown forward input `{}`, stop passing sampling inputs",
+                        name
+                    );
+                    break;
+                }
+            };
+            inputs.push(input);
+        }
+        inputs
+    }
+
+    const ALL: [SamplingInput; 4] = [
+        SamplingInput::TopK,
+        SamplingInput::TopP,
+        SamplingInput::Temperature,
+        SamplingInput::Speed,
+    ];
+
+    /// Argument name of this input in the TorchScript schema.
+    fn name(self) -> &'static str {
+        match self {
+            SamplingInput::TopK => "top_k",
+            SamplingInput::TopP => "top_p",
+            SamplingInput::Temperature => "temperature",
+            SamplingInput::Speed => "speed",
+        }
+    }
+
+    fn to_tensor(self, params: &InferParams, device: tch::Device) -> Tensor {
+        match self {
+            SamplingInput::TopK => Tensor::from_slice(&[params.top_k]),
+            SamplingInput::TopP => Tensor::from_slice(&[params.top_p]),
+            SamplingInput::Temperature => Tensor::from_slice(&[params.temperature]),
+            SamplingInput::Speed => Tensor::from_slice(&[params.speed]),
+        }
+        .to_device(device)
+    }
+}
+
+/// Name of the prompt `create_speaker` loads and `infer` uses.
+pub const DEFAULT_PROMPT: &str = "default";
+
+/// A tensor that is never written after creation, so threads can share it
+/// without a lock.
+#[derive(Debug)]
+struct SharedTensor(Tensor);
+
+// SAFETY: the wrapped tensor is only read. Forward passes do not modify their
+// inputs and `shallow_clone` only bumps the atomic refcount of the storage.
+unsafe impl Sync for SharedTensor {}
+
+impl SharedTensor {
+    fn get(&self) -> Tensor {
+        self.0.shallow_clone()
+    }
+}
+
+/// A reference clip and its text, with the SSL features and phones the model
+/// is conditioned on. Immutable once built, requests share it.
+#[derive(Debug)]
+pub struct SpeakerPrompt {
+    ref_text: String,
+    ref_audio_32k: SharedTensor,
+    ssl_content: SharedTensor,
+    ref_phone_seq: SharedTensor,
+    ref_bert_seq: SharedTensor,
+    /// sha256 of the reference text and audio, see `content_hash`
+    hash: [u8; 32],
+}
+
+impl SpeakerPrompt {
+    pub fn get_ref_text(&self) -> &str {
+        &self.ref_text
+    }
+
+    pub fn get_ref_audio_32k(&self) -> Tensor {
+        self.ref_audio_32k.get()
+    }
+
+    /// Hashes what the prompt was built from, the same however the prompt
+    /// was obtained, built or loaded from the prompt cache.
+    fn content_hash(ref_text: &str, ref_audio_32k: &Tensor) -> anyhow::Result<[u8; 32]> {
+        use sha2::{Digest, Sha256};
+
+        let audio = ref_audio_32k
+            .flatten(0, -1)
+            .to_device(Device::Cpu)
+            .to_kind(tch::Kind::Float);
+        let numel = audio.numel();
+        let mut samples = vec![0f32; numel];
+        audio.f_copy_data(&mut samples, numel)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update((ref_text.len() as u64).to_le_bytes());
+        hasher.update(ref_text);
+        for sample in samples {
+            hasher.update(sample.to_le_bytes());
+        }
+        Ok(hasher.finalize().into())
+    }
+}
+
+/// Identifies a model file by content alone, so copies and hard links of a
+/// file share one module while a file replaced in place is loaded again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ModelKey([u8; 32]);
+
+impl ModelKey {
+    fn of(path: &Path) -> anyhow::Result<Self> {
+        use sha2::{Digest, Sha256};
+
+        let mut file =
+            std::fs::File::open(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(Self(hasher.finalize().into()))
+    }
+}
+
+/// Instances of one TorchScript model. Each forward pass takes an instance for
+/// itself, so at most as many requests as instances run at once and memory
+/// stays bounded by the instance count.
+#[derive(Debug)]
+struct ModulePool<M = tch::CModule> {
+    idle: Mutex<Vec<M>>,
+    returned: Condvar,
+}
+
+impl<M> ModulePool<M> {
+    fn new(modules: Vec<M>) -> Self {
+        Self {
+            idle: Mutex::new(modules),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Waits for an idle instance.
+    fn acquire(&self) -> PooledModule<'_, M> {
+        let mut idle = self.idle.lock().unwrap();
+        loop {
+            if let Some(module) = idle.pop() {
+                return PooledModule {
+                    pool: self,
+                    module: Some(module),
+                };
+            }
+            idle = self.returned.wait(idle).unwrap();
+        }
+    }
+}
+
+/// An instance taken from a `ModulePool`, returned to it on drop.
+struct PooledModule<'a, M = tch::CModule> {
+    pool: &'a ModulePool<M>,
+    module: Option<M>,
+}
+
+impl<M> std::ops::Deref for PooledModule<'_, M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        self.module.as_ref().unwrap()
+    }
+}
+
+impl<M> Drop for PooledModule<'_, M> {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            self.pool.idle.lock().unwrap().push(module);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+/// A TorchScript model shared by every speaker loaded from the same file.
+#[derive(Debug)]
+struct GptModel {
+    key: ModelKey,
+    /// File the model was first loaded from, for logging only.
+    path: PathBuf,
+    modules: ModulePool,
+    sampling_inputs: Vec<SamplingInput>,
+    /// Set when batching is enabled and the model exports a batch method.
+    batcher: Option<Batcher>,
+}
+
+impl Drop for GptModel {
+    fn drop(&mut self) {
+        log::info!("unload model {}", self.path.display());
+    }
+}
+
+/// A loaded model with one or more named reference prompts, e.g. one per
+/// emotion. All prompts share the model.
 #[derive(Debug)]
 pub struct Speaker {
     name: String,
-    ref_text: String,
-    ref_audio_32k: Arc<Mutex<Tensor>>,
-    gpt_sovits: Arc<Mutex<tch::CModule>>,
-    ssl_content: Arc<Mutex<Tensor>>,
-    ref_phone_seq: Arc<Mutex<Tensor>>,
-    ref_bert_seq: Arc<Mutex<Tensor>>,
+    gpt_sovits: Arc<GptModel>,
+    default_prompt: Arc<SpeakerPrompt>,
+    /// Prompts other than the default one.
+    prompts: RwLock<HashMap<String, Arc<SpeakerPrompt>>>,
 }
 
 impl Speaker {
@@ -82,35 +485,209 @@ impl Speaker {
     }
 
     pub fn get_ref_text(&self) -> &str {
-        &self.ref_text
+        self.default_prompt.get_ref_text()
     }
 
     pub fn get_ref_audio_32k(&self) -> Tensor {
-        self.ref_audio_32k.lock().unwrap().shallow_clone()
-    }
-
-    pub fn infer(&self, text_phone_seq: &Tensor, bert_seq: &Tensor) -> anyhow::Result<Tensor> {
-        let gpt_sovits = self.gpt_sovits.lock().unwrap();
-        let ref_audio_32k = self.ref_audio_32k.lock().unwrap();
-        let ssl_content = self.ssl_content.lock().unwrap();
-        let ref_phone_seq = self.ref_phone_seq.lock().unwrap();
-        let ref_bert_seq = self.ref_bert_seq.lock().unwrap();
-        
-        // Create top_k tensor with value 5
-        let top_k = Tensor::from_slice(&[5i64]).to_device(ssl_content.device());
-        
-        let output = gpt_sovits.forward_ts(&[
-            &ssl_content.shallow_clone(),
-            &ref_audio_32k.shallow_clone(),
-            &ref_phone_seq.shallow_clone(),
-            &text_phone_seq.shallow_clone(),
-            &ref_bert_seq.shallow_clone(),
-            &bert_seq.shallow_clone(),
-            &top_k,
-        ])?;
-        
+        self.default_prompt.get_ref_audio_32k()
+    }
+
+    pub fn get_prompt(&self, name: &str) -> Option<Arc<SpeakerPrompt>> {
+        if name == DEFAULT_PROMPT {
+            return Some(self.default_prompt.clone());
+        }
+        self.prompts.read().unwrap().get(name).cloned()
+    }
+
+    /// Adds a prompt, replacing any prompt with the same name. The default
+    /// prompt is set when the speaker is loaded and cannot be replaced.
+    pub fn add_prompt(&self, name: &str, prompt: SpeakerPrompt) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            name != DEFAULT_PROMPT,
+            "the {} prompt cannot be replaced",
+            DEFAULT_PROMPT
+        );
+        self.prompts
+            .write()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(prompt));
+        Ok(())
+    }
+
+    /// Removes a prompt, returns false if there was none with that name. The
+    /// default prompt cannot be removed.
+    pub fn remove_prompt(&self, name: &str) -> bool {
+        self.prompts.write().unwrap().remove(name).is_some()
+    }
+
+    /// Prompt names, sorted, with the default prompt first.
+    pub fn list_prompts(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.prompts.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names.insert(0, DEFAULT_PROMPT.to_string());
+        names
+    }
+
+    pub fn infer(
+        &self,
+        text_phone_seq: &Tensor,
+        bert_seq: &Tensor,
+        params: &InferParams,
+    ) -> anyhow::Result<Tensor> {
+        self.infer_with_prompt(DEFAULT_PROMPT, text_phone_seq, bert_seq, params)
+    }
+
+    pub fn infer_with_prompt(
+        &self,
+        prompt: &str,
+        text_phone_seq: &Tensor,
+        bert_seq: &Tensor,
+        params: &InferParams,
+    ) -> anyhow::Result<Tensor> {
+        params.validate()?;
+
+        let prompt = self
+            .get_prompt(prompt)
+            .ok_or_else(|| InferError::PromptNotFound {
+                speaker: self.name.clone(),
+                prompt: prompt.to_string(),
+            })?;
+
+        let sampling_inputs = &self.gpt_sovits.sampling_inputs;
+
+        let default = InferParams::default();
+        if !sampling_inputs.contains(&SamplingInput::TopP) && params.top_p != default.top_p {
+            log::debug!("{}: model does not accept top_p, ignored", self.name);
+        }
+        if !sampling_inputs.contains(&SamplingInput::Temperature)
+            && params.temperature != default.temperature
+        {
+            log::debug!("{}: model does not accept temperature, ignored", self.name);
+        }
+
+        // seeded runs hold the generator alone, they are never batched
+        if let (Some(batcher), None) = (&self.gpt_sovits.batcher, params.seed) {
+            let key = BatchKey {
+                prompt: Arc::as_ptr(&prompt) as usize,
+                top_k: params.top_k,
+                top_p: params.top_p.to_bits(),
+                temperature: params.temperature.to_bits(),
+                speed: params.speed.to_bits(),
+            };
+            let input = BatchInput {
+                phone_seq: text_phone_seq.shallow_clone(),
+                bert_seq: bert_seq.shallow_clone(),
+            };
+            let audio = batcher.infer(key, input, |inputs| {
+                self.forward_batch(&prompt, inputs, params)
+            })?;
+            return self.apply_speed(audio, params);
+        }
+        let audio = self.forward(&prompt, text_phone_seq, bert_seq, params)?;
+        self.apply_speed(audio, params)
+    }
+
+    /// Time-stretches the output of a model without a `speed` input.
+    fn apply_speed(&self, audio: Tensor, params: &InferParams) -> anyhow::Result<Tensor> {
+        let sampling_inputs = &self.gpt_sovits.sampling_inputs;
+        if params.speed == 1.0 || sampling_inputs.contains(&SamplingInput::Speed) {
+            return Ok(audio);
+        }
+        let samples = synthesizer::tensor_to_samples(&audio)?;
+        let stretched = audio::stretch::time_stretch(&samples, audio::SAMPLE_RATE, params.speed);
+        Ok(Tensor::from_slice(&stretched).to_device(audio.device()))
+    }
+
+    fn forward(
+        &self,
+        prompt: &SpeakerPrompt,
+        text_phone_seq: &Tensor,
+        bert_seq: &Tensor,
+        params: &InferParams,
+    ) -> anyhow::Result<Tensor> {
+        let sampling_inputs = &self.gpt_sovits.sampling_inputs;
+        let ssl_content = prompt.ssl_content.get();
+        let device = ssl_content.device();
+        let mut inputs = vec![
+            ssl_content,
+            prompt.ref_audio_32k.get(),
+            prompt.ref_phone_seq.get(),
+            text_phone_seq.shallow_clone(),
+            prompt.ref_bert_seq.get(),
+            bert_seq.shallow_clone(),
+        ];
+        for input in sampling_inputs {
+            inputs.push(input.to_tensor(params, device));
+        }
+
+        // taken before the generator lock, a seeded run waiting for an
+        // instance must not block every other request
+        let gpt_sovits = self.gpt_sovits.modules.acquire();
+        let output = match params.seed {
+            Some(seed) => {
+                let _rng = SAMPLING_RNG.write().unwrap();
+                tch::manual_seed(seed as i64);
+                gpt_sovits.forward_ts(&inputs)?
+            }
+            None => {
+                let _rng = SAMPLING_RNG.read().unwrap();
+                gpt_sovits.forward_ts(&inputs)?
+            }
+        };
+
         Ok(output.try_into()?)
     }
+
+    /// Runs several texts in one pass of the model's batch method. A batch of
+    /// one uses `forward`.
+    fn forward_batch(
+        &self,
+        prompt: &SpeakerPrompt,
+        inputs: &[BatchInput],
+        params: &InferParams,
+    ) -> anyhow::Result<Vec<Tensor>> {
+        if let [input] = inputs {
+            let audio = self.forward(prompt, &input.phone_seq, &input.bert_seq, params)?;
+            return Ok(vec![audio]);
+        }
+
+        let (text_phone_seq, text_phone_lens, bert_seq) = batch::pad_batch(inputs);
+        let ssl_content = prompt.ssl_content.get();
+        let device = ssl_content.device();
+        let mut args = vec![
+            IValue::Tensor(ssl_content),
+            IValue::Tensor(prompt.ref_audio_32k.get()),
+            IValue::Tensor(prompt.ref_phone_seq.get()),
+            IValue::Tensor(text_phone_seq),
+            IValue::Tensor(text_phone_lens),
+            IValue::Tensor(prompt.ref_bert_seq.get()),
+            IValue::Tensor(bert_seq),
+        ];
+        for input in &self.gpt_sovits.sampling_inputs {
+            args.push(IValue::Tensor(input.to_tensor(params, device)));
+        }
+
+        let gpt_sovits = self.gpt_sovits.modules.acquire();
+        let output = {
+            let _rng = SAMPLING_RNG.read().unwrap();
+            gpt_sovits.method_is(batch::BATCH_METHOD, &args)?
+        };
+        match output {
+            IValue::Tuple(outputs) => match outputs.as_slice() {
+                [IValue::Tensor(audio), IValue::Tensor(audio_lens)] => {
+                    batch::split_batch(audio, audio_lens)
+                }
+                _ => Err(anyhow::anyhow!(
+                    "{} returned an unexpected tuple",
+                    batch::BATCH_METHOD
+                )),
+            },
+            _ => Err(anyhow::anyhow!(
+                "{} did not return a tuple",
+                batch::BATCH_METHOD
+            )),
+        }
+    }
 }
 
 pub struct GPTSovits {
@@ -120,7 +697,12 @@ pub struct GPTSovits {
     symbols: HashMap<String, i64>,
     ssl: tch::CModule,
 
-    speakers: HashMap<String, Speaker>,
+    speakers: RwLock<HashMap<String, Arc<Speaker>>>,
+    /// Models held by speakers, dropped with the last speaker using them.
+    models: Mutex<HashMap<ModelKey, Weak<GptModel>>>,
+    prompt_cache: Option<PromptCache>,
+    model_instances: usize,
+    batching: Option<(usize, u64)>,
 
     jieba: jieba_rs::Jieba,
 }
@@ -139,23 +721,167 @@ impl GPTSovits {
             g2pw,
             device,
             symbols,
-            speakers: HashMap::new(),
+            speakers: RwLock::new(HashMap::new()),
+            models: Mutex::new(HashMap::new()),
+            prompt_cache: None,
+            model_instances: 1,
+            batching: None,
             ssl,
             jieba,
         }
     }
 
+    /// Loads a speaker with a default prompt, replacing any speaker with the
+    /// same name. Requests already running on the old one finish with it.
     pub fn create_speaker(
-        &mut self,
+        &self,
         name: &str,
         gpt_sovits_path: &str,
         ref_audio_samples: &[f32],
         ref_audio_sr: usize,
         ref_text: &str,
     ) -> anyhow::Result<()> {
-        let mut gpt_sovits = tch::CModule::load_on_device(gpt_sovits_path, self.device)?;
-        gpt_sovits.set_eval();
+        let speaker = self.load_speaker(
+            name,
+            gpt_sovits_path,
+            ref_audio_samples,
+            ref_audio_sr,
+            ref_text,
+        )?;
+        self.insert_speaker(speaker);
+        Ok(())
+    }
+
+    /// Loads a speaker with a default prompt without registering it, so more
+    /// prompts can be added before `insert_speaker` makes it visible.
+    pub fn load_speaker(
+        &self,
+        name: &str,
+        gpt_sovits_path: &str,
+        ref_audio_samples: &[f32],
+        ref_audio_sr: usize,
+        ref_text: &str,
+    ) -> anyhow::Result<Speaker> {
+        let gpt_sovits = self.load_model(gpt_sovits_path)?;
 
+        let prompt = self.build_prompt(ref_audio_samples, ref_audio_sr, ref_text)?;
+
+        Ok(Speaker {
+            name: name.to_string(),
+            gpt_sovits,
+            default_prompt: Arc::new(prompt),
+            prompts: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the loaded model for a file, loading `model_instances` copies
+    /// of it if no speaker uses it.
+    fn load_model(&self, gpt_sovits_path: &str) -> anyhow::Result<Arc<GptModel>> {
+        let path = Path::new(gpt_sovits_path);
+        let key = ModelKey::of(path)?;
+
+        // held while loading, so the same file is never loaded twice
+        let mut models = self.models.lock().unwrap();
+        models.retain(|_, model| model.strong_count() > 0);
+        if let Some(model) = models.get(&key).and_then(Weak::upgrade) {
+            log::debug!(
+                "reuse model {} for {}",
+                model.path.display(),
+                path.display()
+            );
+            return Ok(model);
+        }
+
+        log::info!("load model {} x{}", path.display(), self.model_instances);
+        let mut modules = vec![];
+        for _ in 0..self.model_instances {
+            let mut module = tch::CModule::load_on_device(path, self.device)?;
+            module.set_eval();
+            modules.push(module);
+        }
+        let sampling_inputs = SamplingInput::detect(&modules[0]);
+
+        let batcher = match self.batching {
+            Some((max_batch_size, max_wait_ms)) => {
+                match method_arg_names(&modules[0], batch::BATCH_METHOD) {
+                    Some(names) if batch::accepts(&names, &sampling_inputs) => Some(Batcher::new(
+                        max_batch_size,
+                        std::time::Duration::from_millis(max_wait_ms),
+                    )),
+                    Some(names) => {
+                        log::warn!(
+                            "{} has an unexpected {} signature {:?}, not batched",
+                            path.display(),
+                            batch::BATCH_METHOD,
+                            names
+                        );
+                        None
+                    }
+                    None => {
+                        log::info!(
+                            "{} has no {} method, not batched",
+                            path.display(),
+                            batch::BATCH_METHOD
+                        );
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let model = Arc::new(GptModel {
+            key,
+            path: path.to_path_buf(),
+            modules: ModulePool::new(modules),
+            sampling_inputs,
+            batcher,
+        });
+        models.insert(key, Arc::downgrade(&model));
+        Ok(model)
+    }
+
+    /// Number of distinct models held by loaded speakers.
+    pub fn loaded_models(&self) -> usize {
+        let models = self.models.lock().unwrap();
+        models
+            .values()
+            .filter(|model| model.strong_count() > 0)
+            .count()
+    }
+
+    /// Registers a speaker, replacing any speaker with the same name.
+    pub fn insert_speaker(&self, speaker: Speaker) {
+        self.speakers
+            .write()
+            .unwrap()
+            .insert(speaker.name.clone(), Arc::new(speaker));
+    }
+
+    /// Adds a prompt to a loaded speaker, reusing its model.
+    pub fn add_prompt(
+        &self,
+        speaker: &str,
+        prompt_name: &str,
+        ref_audio_samples: &[f32],
+        ref_audio_sr: usize,
+        ref_text: &str,
+    ) -> anyhow::Result<()> {
+        let speaker = self
+            .get_speaker(speaker)
+            .ok_or_else(|| InferError::SpeakerNotFound(speaker.to_string()))?;
+        let prompt = self.build_prompt(ref_audio_samples, ref_audio_sr, ref_text)?;
+        speaker.add_prompt(prompt_name, prompt)
+    }
+
+    /// Runs the SSL model and the text frontend on a reference clip, or reads
+    /// their output from the prompt cache when the clip was seen before.
+    pub fn build_prompt(
+        &self,
+        ref_audio_samples: &[f32],
+        ref_audio_sr: usize,
+        ref_text: &str,
+    ) -> anyhow::Result<SpeakerPrompt> {
         // Avoid skipping first character
         let ref_text = if !ref_text.ends_with(['。', '.']) {
             ref_text.to_string() + "."
@@ -163,6 +889,16 @@ impl GPTSovits {
             ref_text.to_string()
         };
 
+        let cache_key = self
+            .prompt_cache
+            .as_ref()
+            .map(|cache| cache.key(ref_audio_samples, ref_audio_sr, &ref_text));
+        if let (Some(cache), Some(key)) = (&self.prompt_cache, &cache_key) {
+            if let Some(prompt) = cache.load(key, &ref_text, self.device) {
+                return Ok(prompt);
+            }
+        }
+
         let ref_audio = Tensor::from_slice(ref_audio_samples)
             .to_device(self.device)
             .unsqueeze(0);
@@ -170,24 +906,65 @@ impl GPTSovits {
         let ref_audio_16k = self.resample(&ref_audio, ref_audio_sr, 16000)?;
         let ref_audio_32k = self.resample(&ref_audio, ref_audio_sr, 32000)?;
 
-        tch::no_grad(|| {
+        let prompt = tch::no_grad(|| {
             let ssl_content = self.ssl.forward_ts(&[&ref_audio_16k])?;
 
             let (ref_phone_seq, ref_bert_seq) = text::get_phone_and_bert(self, &ref_text)?;
+            let hash = SpeakerPrompt::content_hash(&ref_text, &ref_audio_32k)?;
 
-            let speaker = Speaker {
-                name: name.to_string(),
-                gpt_sovits: Arc::new(Mutex::new(gpt_sovits)),
+            Ok(SpeakerPrompt {
                 ref_text,
-                ssl_content: Arc::new(Mutex::new(ssl_content)),
-                ref_audio_32k: Arc::new(Mutex::new(ref_audio_32k)),
-                ref_phone_seq: Arc::new(Mutex::new(ref_phone_seq)),
-                ref_bert_seq: Arc::new(Mutex::new(ref_bert_seq)),
-            };
+                hash,
+                ref_audio_32k: SharedTensor(ref_audio_32k),
+                ssl_content: SharedTensor(ssl_content),
+                ref_phone_seq: SharedTensor(ref_phone_seq),
+                ref_bert_seq: SharedTensor(ref_bert_seq),
+            })
+        })?;
 
-            self.speakers.insert(name.to_string(), speaker);
-            Ok(())
-        })
+        // the prompt is still usable when it cannot be stored
+        if let (Some(cache), Some(key)) = (&self.prompt_cache, &cache_key) {
+            if let Err(e) = cache.save(key, &prompt) {
+                log::warn!("save prompt cache failed: {}", e);
+            }
+        }
+        Ok(prompt)
+    }
+
+    /// Unloads a speaker, returns false if there was none with that name.
+    pub fn remove_speaker(&self, name: &str) -> bool {
+        self.speakers.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn get_speaker(&self, name: &str) -> Option<Arc<Speaker>> {
+        self.speakers.read().unwrap().get(name).cloned()
+    }
+
+    /// Hex sha256 of the model file and the reference text and audio behind a
+    /// speaker prompt. It changes whenever either is replaced, so audio cached
+    /// under it is never served for a different voice.
+    pub fn fingerprint(&self, speaker: &str, prompt: &str) -> anyhow::Result<String> {
+        use sha2::{Digest, Sha256};
+
+        let speaker = self
+            .get_speaker(speaker)
+            .ok_or_else(|| InferError::SpeakerNotFound(speaker.to_string()))?;
+        let speaker_prompt =
+            speaker
+                .get_prompt(prompt)
+                .ok_or_else(|| InferError::PromptNotFound {
+                    speaker: speaker.name.clone(),
+                    prompt: prompt.to_string(),
+                })?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(speaker.gpt_sovits.key.0);
+        hasher.update(speaker_prompt.hash);
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    pub fn list_speakers(&self) -> Vec<String> {
+        self.speakers.read().unwrap().keys().cloned().collect()
     }
 
     pub fn resample(&self, audio: &Tensor, sr: usize, target_sr: usize) -> anyhow::Result<Tensor> {
@@ -206,18 +983,122 @@ impl GPTSovits {
     }
 
     /// generate a audio tensor from text
-    pub fn infer(&self, speaker: &str, target_text: &str) -> anyhow::Result<Tensor> {
+    pub fn infer(
+        &self,
+        speaker: &str,
+        target_text: &str,
+        params: &InferParams,
+    ) -> anyhow::Result<Tensor> {
+        self.infer_with_prompt(speaker, DEFAULT_PROMPT, target_text, params)
+    }
+
+    /// generate a audio tensor from text, conditioned on a named prompt of the
+    /// speaker
+    pub fn infer_with_prompt(
+        &self,
+        speaker: &str,
+        prompt: &str,
+        target_text: &str,
+        params: &InferParams,
+    ) -> anyhow::Result<Tensor> {
         log::debug!("start infer");
         tch::no_grad(|| {
             let speaker = self
-                .speakers
-                .get(speaker)
-                .ok_or_else(|| anyhow::anyhow!("speaker not found"))?;
+                .get_speaker(speaker)
+                .ok_or_else(|| InferError::SpeakerNotFound(speaker.to_string()))?;
 
             let (phone_seq, bert_seq) = text::get_phone_and_bert(self, target_text)?;
 
-            let audio = speaker.infer(&phone_seq, &bert_seq)?;
+            let audio = speaker.infer_with_prompt(prompt, &phone_seq, &bert_seq, params)?;
             Ok(audio)
         })
     }
 }
+
+/// Needs the models: set `GPT_SOVITS_TEST_VOICE` to a voice directory and run
+/// from the repo root with the files under `resource/`, skipped otherwise.
+#[test]
+fn test_seed_is_deterministic() {
+    let Some(voice_dir) = std::env::var_os("GPT_SOVITS_TEST_VOICE") else {
+        return;
+    };
+    let voice = voice_manager::VoiceModel::new("test", PathBuf::from(voice_dir));
+    let gpt_sovits = GPTSovitsConfig::new("resource/ssl_model.pt".to_string())
+        .with_chinese(
+            "resource/g2pw.pt".to_string(),
+            "resource/bert_model.pt".to_string(),
+            "resource/tokenizer.json".to_string(),
+        )
+        .build(Device::cuda_if_available())
+        .unwrap();
+    voice.load(&gpt_sovits).unwrap();
+
+    let infer = |seed| {
+        let params = InferParams {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let audio = gpt_sovits.infer("test", "今天天气很好。", &params).unwrap();
+        synthesizer::tensor_to_samples(&audio).unwrap()
+    };
+    assert_eq!(infer(42), infer(42));
+    assert_ne!(infer(42), infer(43));
+}
+
+#[test]
+fn test_module_pool() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    const INSTANCES: usize = 3;
+    let pool = ModulePool::new((0..INSTANCES).collect::<Vec<usize>>());
+
+    // every instance can be held at once, the acquires don't wait on each other
+    let barrier = Barrier::new(INSTANCES);
+    let mut taken: Vec<usize> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..INSTANCES)
+            .map(|_| {
+                s.spawn(|| {
+                    let module = pool.acquire();
+                    barrier.wait();
+                    *module
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    taken.sort();
+    assert_eq!(taken, vec![0, 1, 2]);
+
+    // one more acquire waits until an instance is returned
+    let held: Vec<_> = (0..INSTANCES).map(|_| pool.acquire()).collect();
+    let acquired = AtomicBool::new(false);
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let _module = pool.acquire();
+            acquired.store(true, Ordering::SeqCst);
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!acquired.load(Ordering::SeqCst));
+        drop(held);
+    });
+    assert!(acquired.load(Ordering::SeqCst));
+    assert_eq!(pool.idle.lock().unwrap().len(), INSTANCES);
+}
+
+#[test]
+fn test_parse_declaration() {
+    let err = "forward() is missing value for argument 'ssl_content'. Declaration: \
+        forward(__torch__.GPT_SoVITS self, Tensor ssl_content, Tensor ref_audio_sr, \
+        Tensor ref_seq, Tensor text_seq, Tensor ref_bert, Tensor text_bert, Tensor top_k) -> Tensor";
+    let names = parse_declaration(err, "forward").unwrap();
+    assert_eq!(names.len(), FORWARD_BASE_INPUTS + 1);
+    assert_eq!(names[0], "ssl_content");
+    assert_eq!(names[FORWARD_BASE_INPUTS], "top_k");
+
+    // only the declaration of the method asked for counts
+    assert!(parse_declaration(err, batch::BATCH_METHOD).is_none());
+    let err = "Method 'infer_batch' is not defined.";
+    assert!(parse_declaration(err, batch::BATCH_METHOD).is_none());
+}
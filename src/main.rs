@@ -1,18 +1,34 @@
 use actix_cors::Cors;
-use actix_web::{web, App, HttpResponse, HttpServer, Result};
-use gpt_sovits_rs::{voice_manager::VoiceManager, GPTSovits, GPTSovitsConfig};
-use hex::encode;
+use actix_web::http::{header, StatusCode};
+use actix_web::{
+    web, App, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpServer, ResponseError,
+    Result,
+};
+use cache::{CacheConfig, CacheKey, CacheManager, CachedChunks};
+use gpt_sovits_rs::audio::{AudioFormat, StreamEncoder, SAMPLE_RATE};
+use gpt_sovits_rs::{
+    synthesizer::{ChunkCache, NoChunkCache, SynthesisOptions},
+    voice_manager::{VoiceManager, VoiceModel, DEFAULT_EMOTION},
+    GPTSovits, GPTSovitsConfig, InferError, InferParams,
+};
+use prewarm::{Prewarm, PrewarmProgress};
+use scheduler::{ScheduleError, Scheduler};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use sha2::{Digest, Sha256};
 use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{fs, path::Path};
-use tch::Tensor;
 use toml;
 
-#[derive(Debug, Serialize, Deserialize)]
+mod admin;
+mod cache;
+mod prewarm;
+mod scheduler;
+mod watcher;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct TTSRequest {
     character: Option<String>,
     emotion: Option<String>,
@@ -21,262 +37,946 @@ struct TTSRequest {
     top_k: Option<i32>,
     top_p: Option<f32>,
     temperature: Option<f32>,
+    seed: Option<u64>,
     batch_size: Option<i32>,
     speed: Option<f32>,
+    // 句末（。！？）与句中（，）的停顿，单位毫秒
+    sentence_pause_ms: Option<u32>,
+    clause_pause_ms: Option<u32>,
     save_temp: Option<bool>,
     stream: Option<bool>,
     format: Option<String>,
 }
+impl TTSRequest {
+    // 用请求中的参数覆盖 defaults，并检查取值范围
+    fn infer_params(&self, defaults: InferParams) -> anyhow::Result<InferParams> {
+        let mut params = defaults;
+        if let Some(top_k) = self.top_k {
+            params.top_k = top_k as i64;
+        }
+        if let Some(top_p) = self.top_p {
+            params.top_p = top_p;
+        }
+        if let Some(temperature) = self.temperature {
+            params.temperature = temperature;
+        }
+        if let Some(speed) = self.speed {
+            params.speed = speed;
+        }
+        params.seed = self.seed;
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+// OpenAI 兼容的 /v1/audio/speech 请求体
+#[derive(Debug, Deserialize)]
+struct SpeechRequest {
+    model: String,
+    input: String,
+    voice: String,
+    response_format: Option<String>,
+    speed: Option<f32>,
+}
+
 struct AppState {
     gpt_sovits: Arc<GPTSovits>,
     voice_manager: Arc<RwLock<VoiceManager>>,
+    admin_token: Option<String>,
+    // 管理接口正在安装的声音
+    install_locks: admin::InstallLocks,
+    scheduler: Scheduler,
+    // 未指定 seed 的请求是否随机取一个 seed 并通过 X-Seed 返回
+    random_seed: bool,
+    // 配置了 prewarm_file 时的预热进度
+    prewarm: Option<Arc<PrewarmProgress>>,
 }
 
-// 缓存管理结构体
-struct CacheManager {
-    cache_dir: String,
-}
+// 请求 ID 的请求/响应头
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
 
-impl CacheManager {
-    fn new(cache_dir: &str) -> Self {
-        // 确保缓存目录存在
-        if !Path::new(cache_dir).exists() {
-            fs::create_dir_all(cache_dir).unwrap_or_else(|e| {
-                log::warn!("无法创建缓存目录 {}: {}", cache_dir, e);
-            });
-        }
+// 请求 ID：优先使用客户端传入的 X-Request-Id，否则生成一个，随错误信息和日志一起返回
+#[derive(Debug, Clone)]
+struct RequestId(String);
 
-        CacheManager {
-            cache_dir: cache_dir.to_string(),
+impl RequestId {
+    fn of(req: &HttpRequest) -> Self {
+        if let Some(request_id) = req.extensions().get::<RequestId>() {
+            return request_id.clone();
         }
-    }
 
-    // 生成缓存文件名
-    fn get_cache_filename(&self, text: &str, speaker: &str) -> String {
-        let input = format!("{}{}", text, speaker);
-        let mut hasher = Sha256::new();
-        hasher.update(input.as_bytes());
-        let hash = encode(hasher.finalize());
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= 128)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                format!("{:x}-{:04x}", now, COUNTER.fetch_add(1, Ordering::Relaxed))
+            });
 
-        format!("{}/{}.wav", self.cache_dir, hash)
+        let request_id = RequestId(request_id);
+        req.extensions_mut().insert(request_id.clone());
+        request_id
     }
 
-    // 检查缓存是否存在
-    fn cache_exists(&self, filename: &str) -> bool {
-        Path::new(filename).exists()
+    fn as_str(&self) -> &str {
+        &self.0
     }
+}
 
-    // 从缓存加载音频
-    fn load_from_cache(&self, filename: &str) -> Option<Vec<f32>> {
-        if self.cache_exists(filename) {
-            log::debug!("找到缓存文件: {}", filename);
-            match std::fs::File::open(filename) {
-                Ok(file) => match wav_io::read_from_file(file) {
-                    Ok((_, samples)) => {
-                        log::info!("从缓存加载音频: {}", filename);
-                        return Some(samples);
-                    }
-                    Err(e) => {
-                        log::warn!("读取缓存文件失败: {}", e);
-                    }
-                },
-                Err(e) => {
-                    log::warn!("打开缓存文件失败: {}", e);
-                }
-            }
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        std::future::ready(Ok(RequestId::of(req)))
+    }
+}
+
+// 统一的 JSON 错误响应：{"code": ..., "message": ..., "request_id": ...}
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    request_id: String,
+    // 队列满时告诉客户端多少秒后重试
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    fn new(
+        request_id: &RequestId,
+        status: StatusCode,
+        code: &'static str,
+        message: impl ToString,
+    ) -> Self {
+        let message = message.to_string();
+        if status.is_server_error() {
+            log::error!("[{}] {}", request_id.as_str(), message);
         } else {
-            log::debug!("缓存未命中: {}", filename);
+            log::warn!("[{}] {}: {}", request_id.as_str(), code, message);
+        }
+        ApiError {
+            status,
+            code,
+            message,
+            request_id: request_id.0.clone(),
+            retry_after: None,
         }
-        None
     }
 
-    // 保存音频到缓存
-    fn save_to_cache(&self, filename: &str, samples: &Vec<f32>) {
-        let header = wav_io::new_header(32000, 16, false, true);
-        match std::fs::File::create(filename) {
-            Ok(mut file) => {
-                if let Err(e) = wav_io::write_to_file(&mut file, &header, samples) {
-                    log::warn!("写入缓存文件失败: {}", e);
-                } else {
-                    log::info!("已保存音频到缓存: {}", filename);
-                }
-            }
-            Err(e) => {
-                log::warn!("创建缓存文件失败: {}", e);
-            }
-        }
+    fn bad_request(request_id: &RequestId, message: impl ToString) -> Self {
+        Self::new(request_id, StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    fn invalid_format(request_id: &RequestId, message: impl ToString) -> Self {
+        Self::new(
+            request_id,
+            StatusCode::BAD_REQUEST,
+            "invalid_format",
+            message,
+        )
     }
 
-    // 清理缓存目录中的过期文件
-    fn cleanup_cache(&self) {
-        log::info!("开始清理缓存目录: {}", self.cache_dir);
+    fn not_found(request_id: &RequestId, message: impl ToString) -> Self {
+        Self::new(
+            request_id,
+            StatusCode::NOT_FOUND,
+            "speaker_not_found",
+            message,
+        )
+    }
 
-        // 获取当前时间
-        let now = std::time::SystemTime::now();
+    fn invalid_text(request_id: &RequestId, message: impl ToString) -> Self {
+        Self::new(
+            request_id,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_text",
+            message,
+        )
+    }
 
-        // 读取缓存目录
-        let cache_dir = Path::new(&self.cache_dir);
-        if !cache_dir.exists() || !cache_dir.is_dir() {
-            log::warn!("缓存目录不存在或不是目录: {}", self.cache_dir);
-            return;
-        }
+    fn internal(request_id: &RequestId, message: impl ToString) -> Self {
+        Self::new(
+            request_id,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            message,
+        )
+    }
 
-        // 遍历缓存目录中的所有文件
-        if let Ok(entries) = fs::read_dir(cache_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-
-                // 只处理文件
-                if path.is_file() {
-                    // 获取文件的最后修改时间
-                    if let Ok(metadata) = fs::metadata(&path) {
-                        if let Ok(modified) = metadata.modified() {
-                            // 计算文件的年龄（以秒为单位）
-                            if let Ok(duration) = now.duration_since(modified) {
-                                let age_in_seconds = duration.as_secs();
-
-                                // 如果文件超过24小时（86400秒）未修改，则删除
-                                if age_in_seconds > 86400 {
-                                    if let Some(filename) = path.file_name() {
-                                        if let Some(filename_str) = filename.to_str() {
-                                            log::info!("删除过期缓存文件: {}", filename_str);
-                                            if let Err(e) = fs::remove_file(&path) {
-                                                log::warn!(
-                                                    "无法删除缓存文件 {}: {}",
-                                                    filename_str,
-                                                    e
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    fn from_schedule(request_id: &RequestId, e: ScheduleError, scheduler: &Scheduler) -> Self {
+        match e {
+            ScheduleError::QueueFull => ApiError {
+                retry_after: Some(scheduler.retry_after_secs()),
+                ..Self::new(request_id, StatusCode::SERVICE_UNAVAILABLE, "queue_full", e)
+            },
+            ScheduleError::Timeout => {
+                Self::new(request_id, StatusCode::GATEWAY_TIMEOUT, "timeout", e)
             }
-        } else {
-            log::warn!("无法读取缓存目录: {}", self.cache_dir);
         }
+    }
 
-        log::info!("缓存清理完成");
+    // 区分请求本身的问题（说话人或情感不存在、文本无法解析）和推理失败
+    fn from_infer(request_id: &RequestId, e: anyhow::Error) -> Self {
+        match e.downcast_ref::<InferError>() {
+            Some(InferError::SpeakerNotFound(_)) => Self::not_found(request_id, e),
+            Some(InferError::PromptNotFound { .. }) => Self::new(
+                request_id,
+                StatusCode::NOT_FOUND,
+                "emotion_not_found",
+                e.to_string(),
+            ),
+            Some(InferError::InvalidText(_)) => Self::invalid_text(request_id, e),
+            None => Self::internal(request_id, e),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
     }
 }
 
-async fn character_list(data: web::Data<AppState>) -> Result<HttpResponse> {
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        response.insert_header((REQUEST_ID_HEADER, self.request_id.as_str()));
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, retry_after));
+        }
+        response.json(json!({
+            "code": self.code,
+            "message": self.message,
+            "request_id": self.request_id,
+        }))
+    }
+}
+
+// OpenAI 接口对 input 的长度限制
+const MAX_SPEECH_INPUT_CHARS: usize = 4096;
+// OpenAI 接口支持的 response_format，不支持 mp3 和 aac，opus 需要编译时开启 opus 特性
+#[cfg(feature = "opus")]
+const OPENAI_FORMATS: &str = "wav, pcm, flac, opus";
+#[cfg(not(feature = "opus"))]
+const OPENAI_FORMATS: &str = "wav, pcm, flac";
+// OpenAI 接口 response_format=pcm 的采样率
+const OPENAI_PCM_RATE: u32 = 24000;
+
+async fn character_list(
+    request_id: RequestId,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
     // 获取 VoiceManager 的共享引用并立即复制声音列表
     let guard = data.voice_manager.read().map_err(|e| {
-        log::error!("获取 voice_manager 读锁失败: {}", e);
-        actix_web::error::ErrorInternalServerError("无法获取 voice_manager 锁")
+        ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
     })?;
     
-    // 克隆整个列表为 Vec<String>，不再保留对锁的引用
-    let voices: Vec<String> = guard.list_voices().iter().map(|s| s.to_string()).collect();
-    
-    // 丢弃锁
-    drop(guard);
-    
+    // 每个声音的情感列表来自 voice.toml，没有时只有 default
     let mut characters: Value = json!({});
-    for voice in voices {
-        characters[voice] = json!(["default"]);
+    for voice in guard.list_voices() {
+        if let Some(voice_model) = guard.get_voice(voice) {
+            characters[voice] = json!(voice_model.manifest.emotion_names());
+        }
     }
 
+    // 丢弃锁
+    drop(guard);
+
     Ok(HttpResponse::Ok().json(characters))
 }
 
+// 已加载声音的元数据，来自各声音目录中的 voice.toml
+async fn voices(
+    request_id: RequestId,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let guard = data.voice_manager.read().map_err(|e| {
+        ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+    })?;
+
+    let mut names = guard.list_voices();
+    names.sort();
+    let voices: Vec<Value> = names
+        .into_iter()
+        .filter_map(|name| guard.get_voice(name))
+        .map(|voice_model| {
+            let manifest = &voice_model.manifest;
+            json!({
+                "name": voice_model.name,
+                "display_name": manifest.display_name.as_deref().unwrap_or(&voice_model.name),
+                "language": manifest.language,
+                "gender": manifest.gender,
+                "description": manifest.description,
+                "emotions": manifest.emotion_names(),
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({ "voices": voices })))
+}
+
+// 每个声音的加载状态，跳过或加载失败的声音附带原因
+async fn voices_status(
+    request_id: RequestId,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let guard = data.voice_manager.read().map_err(|e| {
+        ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+    })?;
+
+    let voices: Vec<Value> = guard
+        .status()
+        .into_iter()
+        .map(|status| {
+            json!({
+                "name": status.name,
+                "loaded": status.loaded,
+                "error": status.error,
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({ "voices": voices })))
+}
+
+// GET /queue：推理队列长度、排队时间等统计
+async fn queue_status(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.scheduler.status())
+}
+
+// 缓存统计：条目数、大小、命中率和最旧的结果
+async fn cache_stats(
+    request_id: RequestId,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    let stats = cache
+        .lock()
+        .map_err(|e| ApiError::internal(&request_id, format!("获取缓存锁失败: {}", e)))?
+        .stats();
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+// GET /cache/prewarm，未配置预热时返回 404
+async fn prewarm_status(
+    request_id: RequestId,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    match &data.prewarm {
+        Some(progress) => Ok(HttpResponse::Ok().json(progress.to_json())),
+        None => Err(ApiError::not_found(
+            &request_id,
+            "cache prewarm is not configured",
+        )),
+    }
+}
+
+// GET /tts，参数放在 query string 中
 async fn tts(
     req: web::Query<TTSRequest>,
+    request_id: RequestId,
     data: web::Data<AppState>,
     cache: web::Data<Arc<Mutex<CacheManager>>>,
-) -> Result<HttpResponse> {
-    let text_splitter = text_splitter::TextSplitter::new(50);
-    
-    // 同样修复 tts 函数中的读锁问题
-    let guard = data.voice_manager.read().map_err(|e| {
-        log::error!("获取 voice_manager 读锁失败: {}", e);
-        actix_web::error::ErrorInternalServerError("无法获取 voice_manager 锁")
-    })?;
+) -> Result<HttpResponse, ApiError> {
+    handle_tts(&req, &request_id, &data, &cache).await
+}
 
-    // 克隆整个列表为 Vec<String>
-    let voices: Vec<String> = guard.list_voices().iter().map(|s| s.to_string()).collect();
+// POST /tts，参数放在 JSON 请求体中，适合长文本
+async fn tts_json(
+    req: web::Json<TTSRequest>,
+    request_id: RequestId,
+    data: web::Data<AppState>,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    handle_tts(&req, &request_id, &data, &cache).await
+}
 
-    // 丢弃锁
-    drop(guard);
+async fn handle_tts(
+    req: &TTSRequest,
+    request_id: &RequestId,
+    data: &web::Data<AppState>,
+    cache: &web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse, ApiError> {
+    let voice_model = find_voice(data, request_id, req.character.as_deref())?;
 
-    let character = match &req.character {
-        Some(c) => c.as_str(),
-        None => voices
-            .first()
-            .ok_or_else(|| actix_web::error::ErrorInternalServerError("No voices available"))?,
+    // 按 emotion 选择参考音频，未指定时用 default
+    let emotion = match req.emotion.as_deref() {
+        Some(emotion) if !emotion.is_empty() => emotion,
+        _ => DEFAULT_EMOTION,
     };
+    if !voice_model.has_emotion(emotion) {
+        return Err(ApiError::new(
+            request_id,
+            StatusCode::NOT_FOUND,
+            "emotion_not_found",
+            format!(
+                "emotion {} not found for {}, available: {:?}",
+                emotion,
+                voice_model.name,
+                voice_model.manifest.emotion_names()
+            ),
+        ));
+    }
 
     let text = &req.text;
+    if text.trim().is_empty() {
+        return Err(ApiError::invalid_text(request_id, "text must not be empty"));
+    }
 
-    // 检查缓存
-    let cache_filename = match cache.lock() {
-        Ok(cache_guard) => cache_guard.get_cache_filename(text, character),
-        Err(e) => {
-            log::error!("获取缓存锁失败: {}", e);
-            return Err(actix_web::error::ErrorInternalServerError("无法获取缓存锁"));
-        }
+    // voice.toml 中的默认参数，请求中的参数优先
+    let params = req
+        .infer_params(voice_model.manifest.infer_params())
+        .map_err(|e| ApiError::bad_request(request_id, e))?;
+
+    let format = match &req.format {
+        Some(format) => format
+            .parse::<AudioFormat>()
+            .map_err(|e| ApiError::invalid_format(request_id, e))?,
+        None => AudioFormat::Wav16,
     };
 
-    // 尝试从缓存加载
+    let mut options = SynthesisOptions::default();
+    if let Some(sentence_pause_ms) = req.sentence_pause_ms {
+        options.sentence_pause_ms = sentence_pause_ms;
+    }
+    if let Some(clause_pause_ms) = req.clause_pause_ms {
+        options.clause_pause_ms = clause_pause_ms;
+    }
+    options
+        .validate()
+        .map_err(|e| ApiError::bad_request(request_id, e))?;
+
+    synthesize(
+        request_id,
+        data,
+        cache,
+        &voice_model.name,
+        emotion,
+        text,
+        params,
+        options,
+        format,
+        format.output_rate(SAMPLE_RATE),
+        req.stream.unwrap_or(false),
+    )
+    .await
+}
+
+// 未指定 character 时使用第一个声音，读锁只在函数内持有，不跨 await
+fn find_voice(
+    data: &AppState,
+    request_id: &RequestId,
+    character: Option<&str>,
+) -> Result<VoiceModel, ApiError> {
+    let guard = data.voice_manager.read().map_err(|e| {
+        ApiError::internal(request_id, format!("获取 voice_manager 读锁失败: {}", e))
+    })?;
+
+    match character {
+        Some(c) => guard
+            .get_voice(c)
+            .cloned()
+            .ok_or_else(|| ApiError::not_found(request_id, format!("speaker not found: {}", c))),
+        None => guard
+            .list_voices()
+            .first()
+            .and_then(|voice| guard.get_voice(voice))
+            .cloned()
+            .ok_or_else(|| ApiError::not_found(request_id, "No voices available")),
+    }
+}
+
+// 合成音频：查缓存、分段推理、编码为请求的格式，/tts 与 /v1/audio/speech 共用
+// emotion 是声音的参考音频（prompt）名
+#[allow(clippy::too_many_arguments)]
+async fn synthesize(
+    request_id: &RequestId,
+    data: &web::Data<AppState>,
+    cache: &web::Data<Arc<Mutex<CacheManager>>>,
+    character: &str,
+    emotion: &str,
+    text: &str,
+    params: InferParams,
+    options: SynthesisOptions,
+    format: AudioFormat,
+    output_rate: u32,
+    stream: bool,
+) -> Result<HttpResponse, ApiError> {
+    // 检查缓存，缓存键包含所有影响输出的参数以及模型和参考音频的哈希
+    let fingerprint = data
+        .gpt_sovits
+        .fingerprint(character, emotion)
+        .map_err(|e| ApiError::from_infer(request_id, e))?;
+    let cache_key = CacheKey::new(
+        fingerprint.clone(),
+        character,
+        emotion,
+        text,
+        params,
+        options.clone(),
+    );
+
+    // 尝试从缓存加载，未指定 seed 的请求命中任意一次合成的结果，X-Seed 为当时使用的 seed
+    // 读文件在阻塞线程中进行
     let cached_samples = {
-        match cache.lock() {
-            Ok(cache_guard) => cache_guard.load_from_cache(&cache_filename),
-            Err(e) => {
-                log::error!("获取缓存锁失败: {}", e);
+        let (cache, cache_key) = (cache.get_ref().clone(), cache_key.clone());
+        web::block(move || cache::load(&cache, &cache_key))
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("读取缓存失败: {}", e);
                 None
-            }
-        }
+            })
     };
 
-    if let Some(samples) = cached_samples {
+    if let Some((samples, seed)) = cached_samples {
         // 返回缓存的音频
-        let header = wav_io::new_header(32000, 16, false, true);
-        let wav_data = wav_io::write_to_bytes(&header, &samples)
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        return Ok(HttpResponse::Ok().content_type("audio/wav").body(wav_data));
+        let audio_data = format
+            .encode_at(&samples, SAMPLE_RATE, output_rate)
+            .map_err(|e| ApiError::internal(request_id, e))?;
+        let mut response = HttpResponse::Ok();
+        response.content_type(format.content_type());
+        response.insert_header((REQUEST_ID_HEADER, request_id.as_str()));
+        if let Some(seed) = seed {
+            response.insert_header(("X-Seed", seed.to_string()));
+        }
+        return Ok(response.body(audio_data));
+    }
+
+    let (params, chunks) = prepare_inference(
+        request_id,
+        data,
+        cache,
+        fingerprint,
+        character,
+        emotion,
+        text,
+        params,
+        &options,
+    )?;
+
+    if stream {
+        let encoder = format
+            .stream_encoder_at(SAMPLE_RATE, output_rate)
+            .ok_or_else(|| {
+                ApiError::bad_request(request_id, format!("{:?} 格式不支持流式返回", format))
+            })?;
+        return tts_stream(
+            request_id.clone(),
+            data,
+            character.to_string(),
+            emotion.to_string(),
+            text.to_string(),
+            options,
+            params,
+            encoder,
+            cache.get_ref().clone(),
+            cache_key,
+            chunks,
+        );
     }
 
-    let timer = Instant::now();
+    let samples = infer_and_cache(
+        request_id, data, cache, cache_key, character, emotion, text, params, options, chunks,
+    )
+    .await?;
 
-    let mut audios = vec![];
+    let audio_data = format
+        .encode_at(&samples, SAMPLE_RATE, output_rate)
+        .map_err(|e| ApiError::internal(request_id, e))?;
 
-    for target_text in text_splitter.chunks(&text) {
-        log::info!("text: {}", target_text);
-        if target_text == "。" {
-            continue;
-        }
-        let audio = data
-            .gpt_sovits
-            .infer(character, target_text)
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        audios.push(audio);
+    let mut response = HttpResponse::Ok();
+    response.content_type(format.content_type());
+    response.insert_header((REQUEST_ID_HEADER, request_id.as_str()));
+    if let Some(seed) = params.seed {
+        response.insert_header(("X-Seed", seed.to_string()));
+    }
+    Ok(response.body(audio_data))
+}
+
+// 缓存未命中时的准备：检查文本、未指定 seed 时随机取一个、选择分段缓存
+#[allow(clippy::too_many_arguments)]
+fn prepare_inference(
+    request_id: &RequestId,
+    data: &AppState,
+    cache: &Arc<Mutex<CacheManager>>,
+    fingerprint: String,
+    character: &str,
+    emotion: &str,
+    text: &str,
+    params: InferParams,
+    options: &SynthesisOptions,
+) -> Result<(InferParams, Box<dyn ChunkCache + Send>), ApiError> {
+    // 分段、段间停顿与库中的 infer_long 一致
+    let chunk_count = options.chunking.split(text).len();
+    if chunk_count == 0 {
+        return Err(ApiError::invalid_text(
+            request_id,
+            "text has nothing to synthesize",
+        ));
     }
 
-    log::info!("infer time: {} ms", timer.elapsed().as_millis());
+    // 开启 random_seed 时为未指定 seed 的请求随机取一个，通过 X-Seed 返回，
+    // 客户端可以用它重新生成同样的音频
+    // 只有带 seed 的推理是确定的，X-Seed 只在 params.seed 存在时返回
+    let client_params = params;
+    let mut params = params;
+    if params.seed.is_none() && data.random_seed {
+        params.seed = Some(random_seed());
+    }
 
-    let audio = Tensor::cat(&audios, 0);
-    let audio_size = audio.size1().unwrap() as usize;
-    let mut samples = vec![0f32; audio_size];
-    audio
-        .f_copy_data(&mut samples, audio_size)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    let seed_drawn = params.seed != client_params.seed;
+    let chunks = chunk_cache(
+        cache,
+        fingerprint,
+        character,
+        emotion,
+        client_params,
+        seed_drawn,
+        chunk_count,
+    );
+    Ok((params, chunks))
+}
 
-    // 保存到缓存 - 使用更安全的锁获取方式
-    if let Ok(cache_guard) = cache.lock() {
-        cache_guard.save_to_cache(&cache_filename, &samples);
+// 整个请求未命中时逐段查分段缓存，只推理缓存中没有的分段
+// 分段缓存键按请求中的参数计算，未指定 seed 的请求之间可以共用相同的句子
+// 只有一段的文本与整个请求的结果相同，不再单独缓存分段；seed 由服务端随机选取时，
+// 分段键不会在其他请求中重复，复用其他 seed 的分段又会让 X-Seed 无法重现，也不使用分段缓存
+fn chunk_cache(
+    cache: &Arc<Mutex<CacheManager>>,
+    fingerprint: String,
+    character: &str,
+    emotion: &str,
+    client_params: InferParams,
+    seed_drawn: bool,
+    chunk_count: usize,
+) -> Box<dyn ChunkCache + Send> {
+    if chunk_count > 1 && !seed_drawn {
+        Box::new(CachedChunks::new(
+            cache.clone(),
+            fingerprint,
+            character,
+            emotion,
+            client_params,
+        ))
     } else {
-        log::warn!("无法获取缓存锁，跳过缓存保存");
+        Box::new(NoChunkCache)
     }
+}
 
-    let header = wav_io::new_header(32000, 16, false, true);
-    let wav_data = wav_io::write_to_bytes(&header, &samples)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+// 推理在调度器的工作线程中执行，不阻塞异步线程，结果在阻塞线程中写入缓存
+#[allow(clippy::too_many_arguments)]
+async fn infer_and_cache(
+    request_id: &RequestId,
+    data: &AppState,
+    cache: &Arc<Mutex<CacheManager>>,
+    cache_key: CacheKey,
+    character: &str,
+    emotion: &str,
+    text: &str,
+    params: InferParams,
+    options: SynthesisOptions,
+    mut chunks: Box<dyn ChunkCache + Send>,
+) -> Result<Arc<Vec<f32>>, ApiError> {
+    let gpt_sovits = data.gpt_sovits.clone();
+    let (speaker, prompt, text) = (character.to_string(), emotion.to_string(), text.to_string());
+    let samples = data
+        .scheduler
+        .run(move || {
+            let timer = Instant::now();
+            log::info!("text: {}", text);
+            let mut samples = vec![];
+            let result = gpt_sovits.infer_long_streaming_cached(
+                &speaker,
+                &prompt,
+                &text,
+                &params,
+                &options,
+                chunks.as_mut(),
+                |audio| {
+                    samples.extend(audio);
+                    true
+                },
+            );
+            log::info!("infer time: {} ms", timer.elapsed().as_millis());
+            result.map(|_| samples)
+        })
+        .await
+        .map_err(|e| ApiError::from_schedule(request_id, e, &data.scheduler))?
+        .map_err(|e| ApiError::from_infer(request_id, e))?;
 
-    Ok(HttpResponse::Ok().content_type("audio/wav").body(wav_data))
+    let samples = Arc::new(samples);
+    {
+        let (cache, samples) = (cache.clone(), samples.clone());
+        let saved = web::block(move || cache::save(&cache, &cache_key, &samples, params.seed));
+        if let Err(e) = saved.await {
+            log::warn!("保存缓存失败: {}", e);
+        }
+    }
+    Ok(samples)
+}
+
+// 随机 seed，限制在 53 位以内，JavaScript 客户端读取 X-Seed 时不会丢失精度
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // 每个 RandomState 的 key 都不同，再混入当前时间
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish() & ((1 << 53) - 1)
+}
+
+// OpenAI 风格的错误响应，现有 SDK 能直接解析出错误信息
+fn openai_error(status: StatusCode, message: String, param: Option<&str>) -> HttpResponse {
+    HttpResponse::build(status).json(json!({
+        "error": {
+            "message": message,
+            "type": "invalid_request_error",
+            "param": param,
+            "code": null,
+        }
+    }))
+}
+
+// OpenAI 兼容接口：voice 对应已加载的声音，voice 不存在时再尝试把 model 当作声音名
+async fn speech(
+    req: web::Json<SpeechRequest>,
+    request_id: RequestId,
+    data: web::Data<AppState>,
+    cache: web::Data<Arc<Mutex<CacheManager>>>,
+) -> Result<HttpResponse> {
+    let voice_model = {
+        let guard = data.voice_manager.read().map_err(|e| {
+            ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+        })?;
+        match guard
+            .get_voice(&req.voice)
+            .or_else(|| guard.get_voice(&req.model))
+        {
+            Some(voice_model) => voice_model.clone(),
+            None => {
+                return Ok(openai_error(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Voice '{}' not found, available: {:?}",
+                        req.voice,
+                        guard.list_voices()
+                    ),
+                    Some("voice"),
+                ))
+            }
+        }
+    };
+
+    if req.input.trim().is_empty() {
+        return Ok(openai_error(
+            StatusCode::BAD_REQUEST,
+            "Input must not be empty".to_string(),
+            Some("input"),
+        ));
+    }
+    if req.input.chars().count() > MAX_SPEECH_INPUT_CHARS {
+        return Ok(openai_error(
+            StatusCode::BAD_REQUEST,
+            format!("Input is longer than {} characters", MAX_SPEECH_INPUT_CHARS),
+            Some("input"),
+        ));
+    }
+
+    // 未指定时返回 wav，OpenAI 默认的 mp3 与 aac 不支持，请求时返回 400 并列出可用的格式
+    let format = match &req.response_format {
+        Some(format) => match format.parse::<AudioFormat>() {
+            Ok(format) => format,
+            Err(_) => {
+                return Ok(openai_error(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "response_format {} is not supported, use one of: {}",
+                        format, OPENAI_FORMATS
+                    ),
+                    Some("response_format"),
+                ))
+            }
+        },
+        None => AudioFormat::Wav16,
+    };
+
+    let speed = req.speed.unwrap_or(1.0);
+    if !(0.25..=4.0).contains(&speed) {
+        return Ok(openai_error(
+            StatusCode::BAD_REQUEST,
+            format!("speed must be between 0.25 and 4.0, got {}", speed),
+            Some("speed"),
+        ));
+    }
+    // 未指定时使用 voice.toml 中的语速
+    let mut params = voice_model.manifest.infer_params();
+    if req.speed.is_some() {
+        params.speed = speed;
+    }
+
+    // OpenAI 的 pcm 是 24 kHz 16 位小端，其他格式保持模型的采样率
+    let output_rate = match format {
+        AudioFormat::Pcm16 => OPENAI_PCM_RATE,
+        _ => format.output_rate(SAMPLE_RATE),
+    };
+    // 能流式编码的格式直接边推理边返回
+    let stream = format.stream_encoder(SAMPLE_RATE).is_some();
+    // 错误也按 OpenAI 的格式返回
+    synthesize(
+        &request_id,
+        &data,
+        &cache,
+        &voice_model.name,
+        DEFAULT_EMOTION,
+        &req.input,
+        params,
+        SynthesisOptions::default(),
+        format,
+        output_rate,
+        stream,
+    )
+    .await
+    .or_else(|e| {
+        let mut response = openai_error(e.status, e.message, None);
+        if let Some(retry_after) = e.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        Ok(response)
+    })
+}
+
+// OpenAI 兼容接口：把已加载的声音列为模型
+async fn models(
+    request_id: RequestId,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let guard = data.voice_manager.read().map_err(|e| {
+        ApiError::internal(&request_id, format!("获取 voice_manager 读锁失败: {}", e))
+    })?;
+
+    let models: Vec<Value> = guard
+        .list_voices()
+        .iter()
+        .map(|voice| {
+            json!({
+                "id": voice,
+                "object": "model",
+                "created": 0,
+                "owned_by": "gpt_sovits_rs",
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "object": "list",
+        "data": models,
+    })))
+}
+
+// 流式返回：先发送格式头（WAV 头，裸数据格式没有），每段文本推理完成后立即发送该段的音频
+// 整个请求作为一个任务排队，队列满时直接返回错误
+#[allow(clippy::too_many_arguments)]
+fn tts_stream(
+    request_id: RequestId,
+    data: &AppState,
+    character: String,
+    emotion: String,
+    text: String,
+    options: SynthesisOptions,
+    params: InferParams,
+    mut encoder: StreamEncoder,
+    cache: Arc<Mutex<CacheManager>>,
+    cache_key: CacheKey,
+    mut chunks: Box<dyn ChunkCache + Send>,
+) -> Result<HttpResponse, ApiError> {
+    let mut response = HttpResponse::Ok();
+    response.content_type(encoder.format().content_type());
+    response.insert_header((REQUEST_ID_HEADER, request_id.as_str()));
+    if let Some(seed) = params.seed {
+        response.insert_header(("X-Seed", seed.to_string()));
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<web::Bytes>>(4);
+
+    let gpt_sovits = data.gpt_sovits.clone();
+    let error_request_id = request_id.clone();
+    let spawned = data.scheduler.spawn(move |ready| {
+        if let Err(e) = ready {
+            log::error!("[{}] 流式推理未开始: {}", request_id.as_str(), e);
+            let _ = tx.blocking_send(Err(std::io::Error::other(e.to_string())));
+            return;
+        }
+        let timer = Instant::now();
+
+        let header = encoder.header();
+        if !header.is_empty() && tx.blocking_send(Ok(web::Bytes::from(header))).is_err() {
+            return;
+        }
+
+        log::info!("text: {}", text);
+        let mut samples = vec![];
+        let mut disconnected = false;
+        let result = gpt_sovits.infer_long_streaming_cached(
+            &character,
+            &emotion,
+            &text,
+            &params,
+            &options,
+            chunks.as_mut(),
+            |chunk_samples| {
+                log::debug!("chunk ready after: {} ms", timer.elapsed().as_millis());
+                if tx
+                    .blocking_send(Ok(web::Bytes::from(encoder.encode(&chunk_samples))))
+                    .is_err()
+                {
+                    disconnected = true;
+                    return false;
+                }
+                samples.extend(chunk_samples);
+                true
+            },
+        );
+        if let Err(e) = result {
+            log::error!("[{}] 流式推理失败: {}", request_id.as_str(), e);
+            let _ = tx.blocking_send(Err(std::io::Error::other(e.to_string())));
+            return;
+        }
+        if disconnected {
+            log::info!("客户端已断开，停止推理");
+            return;
+        }
+
+        log::info!("infer time: {} ms", timer.elapsed().as_millis());
+
+        let tail = encoder.finish();
+        if !tail.is_empty() && tx.blocking_send(Ok(web::Bytes::from(tail))).is_err() {
+            return;
+        }
+
+        cache::save(&cache, &cache_key, &samples, params.seed);
+    });
+    spawned.map_err(|e| ApiError::from_schedule(&error_request_id, e, &data.scheduler))?;
+
+    Ok(response.streaming(tokio_stream::wrappers::ReceiverStream::new(rx)))
 }
 
 // 读取配置文件
@@ -332,14 +1032,37 @@ async fn main() -> std::io::Result<()> {
             env::var("GPT_SOVITS_CACHE_DIR").unwrap_or_else(|_| "/home/itisl/tmp".to_string())
         }
     };
-    let cache_manager = Arc::new(Mutex::new(CacheManager::new(&cache_dir)));
+    // 缓存大小、条目数上限与有效期，内存缓存默认关闭
+    let config_u64 = |name: &str| match config.get(name) {
+        Some(toml::Value::Integer(n)) if *n >= 0 => Some(*n as u64),
+        _ => None,
+    };
+    let default_cache_config = CacheConfig::default();
+    let cache_config = CacheConfig {
+        max_bytes: config_u64("cache_max_mb")
+            .map_or(default_cache_config.max_bytes, |mb| mb * 1024 * 1024),
+        max_entries: config_u64("cache_max_entries")
+            .map_or(default_cache_config.max_entries, |n| n as usize),
+        ttl: config_u64("cache_ttl_secs")
+            .map_or(default_cache_config.ttl, std::time::Duration::from_secs),
+        memory_max_bytes: config_u64("cache_memory_mb")
+            .map_or(default_cache_config.memory_max_bytes, |mb| mb * 1024 * 1024),
+    };
+    let cleanup_interval = config_u64("cache_cleanup_interval_secs")
+        .unwrap_or(7200)
+        .max(60);
+    log::info!("缓存配置: {:?}", cache_config);
+    let cache_manager = Arc::new(Mutex::new(CacheManager::new(&cache_dir, cache_config)));
 
-    // 每隔2小时清理一次缓存
+    // 定期清理过期的缓存
     let cleanup_cache_manager = cache_manager.clone();
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(std::time::Duration::from_secs(7200)).await;
-            cleanup_cache_manager.lock().unwrap().cleanup_cache();
+            tokio::time::sleep(std::time::Duration::from_secs(cleanup_interval)).await;
+            let cache = cleanup_cache_manager.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || cache::cleanup(&cache)).await {
+                log::error!("清理缓存失败: {}", e);
+            }
         }
     });
 
@@ -357,51 +1080,168 @@ async fn main() -> std::io::Result<()> {
         log::error!("Failed to scan voices directory: {}", e);
         return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
     }
-    log::info!(
-        "Available voices: {:?}",
-        voice_manager.read().unwrap().list_voices()
-    );
 
     // Initialize GPT-SoVITS
-    let gpt_config = GPTSovitsConfig::new("resource/ssl_model.pt".to_string()).with_chinese(
-        "resource/g2pw.pt".to_string(),
-        "resource/bert_model.pt".to_string(),
-        "resource/tokenizer.json".to_string(),
-    );
+    // 参考音频的 SSL 与 BERT 特征保存在 prompt_cache_dir，重启时不用重新计算
+    let prompt_cache_dir = match config.get("prompt_cache_dir") {
+        Some(toml::Value::String(dir)) => dir.clone(),
+        _ => format!("{}/prompts", cache_dir),
+    };
+    // 每个模型加载的实例数，同一个声音最多同时处理这么多请求，内存随之增加
+    let model_instances = match config.get("model_instances") {
+        Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+        _ => 1,
+    };
+    // 同一个声音的并发请求合并成一批推理，模型导出了批量推理方法时才生效
+    let max_batch_size = match config.get("max_batch_size") {
+        Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+        _ => 1,
+    };
+    let max_batch_wait_ms = match config.get("max_batch_wait_ms") {
+        Some(toml::Value::Integer(n)) if *n >= 0 => *n as u64,
+        _ => 10,
+    };
+    let gpt_config = GPTSovitsConfig::new("resource/ssl_model.pt".to_string())
+        .with_chinese(
+            "resource/g2pw.pt".to_string(),
+            "resource/bert_model.pt".to_string(),
+            "resource/tokenizer.json".to_string(),
+        )
+        .with_prompt_cache(prompt_cache_dir)
+        .with_model_instances(model_instances)
+        .with_batching(max_batch_size, max_batch_wait_ms);
 
     let device = gpt_sovits_rs::Device::cuda_if_available();
     log::info!("device: {:?}", device);
 
-    let mut gpt_sovits = gpt_config
+    let gpt_sovits = gpt_config
         .build(device)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
     // Initialize speakers
-    for voice in voice_manager.read().unwrap().list_voices() {
-        let voice_ref_wav = format!("voices/{}/ref.wav", voice);
-        let voice_ref_text = fs::read_to_string(Path::new(&format!("voices/{}/ref.txt", voice)))
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-        let file = std::fs::File::open(voice_ref_wav).unwrap();
-        let (head, ref_audio_samples) = wav_io::read_from_file(file).unwrap();
-
-        gpt_sovits
-            .create_speaker(
-                &voice,
-                &format!("voices/{}/gpt_sovits_model.pt", voice),
-                &ref_audio_samples,
-                head.sample_rate as usize,
-                &voice_ref_text,
-            )
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let voice_models: Vec<VoiceModel> = {
+        let guard = voice_manager.read().unwrap();
+        guard
+            .list_voices()
+            .iter()
+            .filter_map(|voice| guard.get_voice(voice).cloned())
+            .collect()
+    };
+    // 单个声音加载失败只跳过该声音，原因可以通过 /voices/status 查看
+    for voice_model in voice_models {
+        if let Err(e) = voice_model.load(&gpt_sovits) {
+            log::error!("加载声音 {} 失败: {}", voice_model.name, e);
+            voice_manager
+                .write()
+                .unwrap()
+                .mark_failed(&voice_model.name, e.to_string());
+        }
     }
+    // 使用同一个模型文件的声音共享一份模型
+    log::info!("已加载 {} 个模型", gpt_sovits.loaded_models());
 
     let gpt_sovits = Arc::new(gpt_sovits);
 
+    // 配置了 admin_token 才开放管理接口
+    let admin_token = match config.get("admin_token") {
+        Some(toml::Value::String(token)) if !token.is_empty() => Some(token.clone()),
+        _ => env::var("GPT_SOVITS_ADMIN_TOKEN")
+            .ok()
+            .filter(|t| !t.is_empty()),
+    };
+    let admin_enabled = admin_token.is_some();
+    if !admin_enabled {
+        log::info!("未配置 admin_token，管理接口已关闭");
+    }
+
+    // 推理线程数、排队上限和单个请求的超时时间
+    // 默认每个已加载模型的每个实例一个线程，且不少于批大小，之后安装的声音不会增加线程数
+    let inference_workers = match config.get("inference_workers") {
+        Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+        _ => (model_instances * gpt_sovits.loaded_models()).max(max_batch_size),
+    };
+    let queue_depth = match config.get("queue_depth") {
+        Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+        _ => 32,
+    };
+    let request_timeout = match config.get("request_timeout_secs") {
+        Some(toml::Value::Integer(n)) if *n > 0 => std::time::Duration::from_secs(*n as u64),
+        _ => std::time::Duration::from_secs(120),
+    };
+    // 一批请求各占一个推理线程等待合并，线程数少于批大小时批次凑不满
+    if max_batch_size > inference_workers {
+        log::warn!(
+            "max_batch_size ({}) 大于 inference_workers ({})，批次最多只有 {} 个请求",
+            max_batch_size,
+            inference_workers,
+            inference_workers
+        );
+    }
+    let scheduler = Scheduler::new(inference_workers, queue_depth, request_timeout)?;
+    log::info!(
+        "推理线程: {}，队列长度: {}，超时: {} 秒",
+        inference_workers,
+        queue_depth,
+        request_timeout.as_secs()
+    );
+
+    // 为未指定 seed 的请求随机取 seed，带 seed 的推理不能并行，默认关闭
+    let random_seed = matches!(config.get("random_seed"), Some(toml::Value::Boolean(true)));
+    if random_seed {
+        log::warn!("已开启 random_seed，所有推理将依次执行，不再并行或批处理");
+    }
+
+    // 缓存预热文件，每行 `声音<TAB>文本`，启动后在后台合成缓存中没有的结果
+    let prewarm_file = match config.get("prewarm_file") {
+        Some(toml::Value::String(path)) if !path.is_empty() => Some(path.clone()),
+        _ => env::var("GPT_SOVITS_PREWARM_FILE")
+            .ok()
+            .filter(|p| !p.is_empty()),
+    };
+    let prewarm_concurrency = match config.get("prewarm_concurrency") {
+        Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+        _ => 1,
+    };
+    let prewarm = prewarm_file.and_then(|path| {
+        Prewarm::load(&path)
+            .map_err(|e| log::error!("无法读取预热文件 {}: {}", path, e))
+            .ok()
+    });
+
     let app_state = web::Data::new(AppState {
         gpt_sovits: gpt_sovits.clone(),
         voice_manager: voice_manager.clone(),
+        admin_token,
+        install_locks: admin::InstallLocks::default(),
+        scheduler,
+        random_seed,
+        prewarm: prewarm.as_ref().map(Prewarm::progress),
     });
+    if let Some(prewarm) = prewarm {
+        prewarm.start(
+            prewarm_concurrency,
+            app_state.clone(),
+            web::Data::new(cache_manager.clone()),
+        );
+    }
+
+    log::info!(
+        "Available voices: {:?}",
+        voice_manager.read().unwrap().list_voices()
+    );
+
+    // 监听 voices 目录的变化，watch_voices = false 时关闭
+    let watch_voices = !matches!(
+        config.get("watch_voices"),
+        Some(toml::Value::Boolean(false))
+    );
+    let _voice_watcher = if watch_voices {
+        watcher::watch_voices(gpt_sovits.clone(), voice_manager.clone())
+            .map_err(|e| log::warn!("无法监听声音目录: {}", e))
+            .ok()
+    } else {
+        None
+    };
 
     log::info!("Starting server at http://localhost:{}", port);
 
@@ -417,9 +1257,163 @@ async fn main() -> std::io::Result<()> {
             .app_data(app_state.clone())
             .app_data(web::Data::new(cache_manager.clone()))
             .route("/character_list", web::get().to(character_list))
+            .route("/voices", web::get().to(voices))
+            .route("/voices/status", web::get().to(voices_status))
+            .route("/queue", web::get().to(queue_status))
+            .route("/cache/stats", web::get().to(cache_stats))
+            .route("/cache/prewarm", web::get().to(prewarm_status))
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|e, req| ApiError::bad_request(&RequestId::of(req), e).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|e, req| ApiError::bad_request(&RequestId::of(req), e).into()),
+            )
             .route("/tts", web::get().to(tts))
+            .route("/tts", web::post().to(tts_json))
+            .route("/v1/audio/speech", web::post().to(speech))
+            .route("/v1/models", web::get().to(models))
+            .configure(|cfg| {
+                if admin_enabled {
+                    admin::configure(cfg);
+                }
+            })
     })
     .bind(format!("0.0.0.0:{}", port))?
     .run()
     .await
 }
+
+#[test]
+fn test_infer_params_from_query() {
+    let parse = |query: &str| {
+        let req = web::Query::<TTSRequest>::from_query(query).unwrap();
+        req.infer_params(InferParams::default())
+    };
+
+    let params = parse("text=hi&top_k=10&top_p=0.8&temperature=0.7&seed=42").unwrap();
+    assert_eq!(params.top_k, 10);
+    assert_eq!(params.top_p, 0.8);
+    assert_eq!(params.temperature, 0.7);
+    assert_eq!(params.seed, Some(42));
+    // unset parameters keep the voice defaults
+    let defaults = InferParams {
+        top_k: 20,
+        ..Default::default()
+    };
+    let req = web::Query::<TTSRequest>::from_query("text=hi&top_p=0.5").unwrap();
+    assert_eq!(req.infer_params(defaults).unwrap().top_k, 20);
+
+    for query in [
+        "text=hi&top_k=0",
+        "text=hi&top_p=0",
+        "text=hi&top_p=1.5",
+        "text=hi&temperature=0",
+        "text=hi&speed=5",
+    ] {
+        assert!(parse(query).is_err(), "{}", query);
+    }
+}
+
+#[actix_web::test]
+async fn test_api_error_response() {
+    use actix_web::body;
+
+    let request_id = RequestId("test-id".to_string());
+    let cases = [
+        (
+            InferError::SpeakerNotFound("nobody".to_string()),
+            StatusCode::NOT_FOUND,
+            "speaker_not_found",
+        ),
+        (
+            InferError::PromptNotFound {
+                speaker: "alice".to_string(),
+                prompt: "angry".to_string(),
+            },
+            StatusCode::NOT_FOUND,
+            "emotion_not_found",
+        ),
+        (
+            InferError::InvalidText("1/0".to_string()),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_text",
+        ),
+    ];
+    for (e, status, code) in cases {
+        let response = ApiError::from_infer(&request_id, e.into()).error_response();
+        assert_eq!(response.status(), status);
+        let header = response.headers().get(REQUEST_ID_HEADER).cloned();
+        assert_eq!(header.unwrap(), "test-id");
+        let body = response.into_body();
+        let body: Value = serde_json::from_slice(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(body["code"], code);
+        assert_eq!(body["request_id"], "test-id");
+    }
+
+    let e = ApiError::from_infer(&request_id, anyhow::anyhow!("libtorch failed"));
+    assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn test_schedule_error_response() {
+    let request_id = RequestId("test-id".to_string());
+    let scheduler = Scheduler::new(1, 1, std::time::Duration::from_secs(1)).unwrap();
+
+    let response =
+        ApiError::from_schedule(&request_id, ScheduleError::QueueFull, &scheduler).error_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+    assert_eq!(retry_after.unwrap(), "1");
+
+    let response =
+        ApiError::from_schedule(&request_id, ScheduleError::Timeout, &scheduler).error_response();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(response.headers().get(header::RETRY_AFTER).is_none());
+}
+
+#[test]
+fn test_chunk_cache_shared_between_unseeded_requests() {
+    let dir = std::env::temp_dir().join(format!("gpt_sovits_chunks_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let cache = Arc::new(Mutex::new(CacheManager::new(
+        dir.to_str().unwrap(),
+        CacheConfig::default(),
+    )));
+    let chunks = |params: InferParams, seed_drawn: bool, chunk_count: usize| {
+        chunk_cache(
+            &cache,
+            "fingerprint".to_string(),
+            "alice",
+            DEFAULT_EMOTION,
+            params,
+            seed_drawn,
+            chunk_count,
+        )
+    };
+    let sentence = "今天天气很好。";
+    let samples = vec![0.5; 100];
+
+    // 两个未指定 seed 的请求共用同一句的结果
+    chunks(InferParams::default(), false, 2).save(sentence, &samples);
+    let loaded = chunks(InferParams::default(), false, 3).load(sentence);
+    assert_eq!(loaded, Some(samples.clone()));
+    assert_eq!(cache.lock().unwrap().stats()["chunk_entries"], 1);
+
+    // 客户端指定的 seed 是键的一部分
+    let seeded = InferParams {
+        seed: Some(42),
+        ..Default::default()
+    };
+    assert!(chunks(seeded, false, 2).load(sentence).is_none());
+
+    // 服务端随机选取 seed 或只有一段时不读写分段缓存
+    let mut drawn = chunks(InferParams::default(), true, 2);
+    assert!(drawn.load(sentence).is_none());
+    drawn.save("另一句话。", &samples);
+    chunks(InferParams::default(), false, 1).save("第三句话。", &samples);
+    assert_eq!(cache.lock().unwrap().stats()["chunk_entries"], 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
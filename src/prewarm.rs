@@ -0,0 +1,259 @@
+// 缓存预热：启动时读取 `声音<TAB>文本` 格式的文件，在后台把缓存中没有的结果合成好，
+// 部署后的第一个请求也能直接命中缓存
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use gpt_sovits_rs::synthesizer::SynthesisOptions;
+use gpt_sovits_rs::voice_manager::DEFAULT_EMOTION;
+use serde_json::{json, Value};
+
+use crate::cache::{CacheKey, CacheManager};
+use crate::{find_voice, infer_and_cache, prepare_inference, ApiError, AppState, RequestId};
+
+// 推理队列已满时最多重试的次数，每次等待的时间加倍
+const QUEUE_FULL_RETRIES: u32 = 5;
+
+// 文件中的一行，没有 TAB 的行使用默认声音，与不指定 character 的 /tts 请求相同
+#[derive(Debug, PartialEq)]
+struct PrewarmItem {
+    line: usize,
+    speaker: Option<String>,
+    text: String,
+}
+
+// 预热进度，/cache/prewarm 返回
+pub struct PrewarmProgress {
+    file: String,
+    total: usize,
+    done: AtomicUsize,
+    // 已在缓存中、不需要合成的条目
+    cached: AtomicUsize,
+    failed: AtomicUsize,
+    started: Instant,
+}
+
+impl PrewarmProgress {
+    pub fn to_json(&self) -> Value {
+        let done = self.done.load(Ordering::Relaxed);
+        json!({
+            "file": self.file,
+            "total": self.total,
+            "done": done,
+            "cached": self.cached.load(Ordering::Relaxed),
+            "failed": self.failed.load(Ordering::Relaxed),
+            "running": done < self.total,
+            "elapsed_secs": self.started.elapsed().as_secs(),
+        })
+    }
+}
+
+pub struct Prewarm {
+    items: Vec<PrewarmItem>,
+    progress: Arc<PrewarmProgress>,
+}
+
+impl Prewarm {
+    // 读取预热文件，跳过空行和 # 开头的注释行
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let items: Vec<PrewarmItem> = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                let (speaker, text) = match line.split_once('\t') {
+                    Some((speaker, text)) if !speaker.trim().is_empty() => {
+                        (Some(speaker.trim().to_string()), text)
+                    }
+                    Some((_, text)) => (None, text),
+                    None => (None, line),
+                };
+                PrewarmItem {
+                    line: i + 1,
+                    speaker,
+                    text: text.trim().to_string(),
+                }
+            })
+            .collect();
+
+        let progress = Arc::new(PrewarmProgress {
+            file: path.to_string(),
+            total: items.len(),
+            done: AtomicUsize::new(0),
+            cached: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            started: Instant::now(),
+        });
+        Ok(Prewarm { items, progress })
+    }
+
+    pub fn progress(&self) -> Arc<PrewarmProgress> {
+        self.progress.clone()
+    }
+
+    // 启动 concurrency 个后台任务依次取出条目，按默认参数合成，与普通 /tts 请求共用缓存和推理队列
+    // 直接走推理路径，不经过 handle_tts，不影响缓存的命中统计和内存缓存
+    pub fn start(
+        self,
+        concurrency: usize,
+        data: web::Data<AppState>,
+        cache: web::Data<Arc<Mutex<CacheManager>>>,
+    ) {
+        log::info!(
+            "开始预热缓存: {}，共 {} 条，并发 {}",
+            self.progress.file,
+            self.items.len(),
+            concurrency
+        );
+        let items = Arc::new(self.items);
+        let next = Arc::new(AtomicUsize::new(0));
+        // 大约每完成 5% 输出一次进度
+        let report_every = (items.len() / 20).max(1);
+
+        for _ in 0..concurrency.min(items.len()) {
+            let (items, next, progress) = (items.clone(), next.clone(), self.progress.clone());
+            let (data, cache) = (data.clone(), cache.clone());
+            actix_web::rt::spawn(async move {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    match prewarm_item(item, &data, &cache).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            progress.cached.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            progress.failed.fetch_add(1, Ordering::Relaxed);
+                            log::warn!("预热第 {} 行失败: {}", item.line, e);
+                        }
+                    }
+
+                    let done = progress.done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if done == progress.total {
+                        log::info!(
+                            "缓存预热完成，共 {} 条，失败 {} 条，耗时 {} 秒",
+                            progress.total,
+                            progress.failed.load(Ordering::Relaxed),
+                            progress.started.elapsed().as_secs()
+                        );
+                    } else if done % report_every == 0 {
+                        log::info!("缓存预热进度: {}/{}", done, progress.total);
+                    }
+                }
+            });
+        }
+    }
+}
+
+// 合成一个条目并写入缓存，已在缓存中时返回 false
+// 参数与只带 character 和 text 的 /tts 请求相同，缓存键也相同
+async fn prewarm_item(
+    item: &PrewarmItem,
+    data: &AppState,
+    cache: &Arc<Mutex<CacheManager>>,
+) -> Result<bool, ApiError> {
+    let request_id = RequestId(format!("prewarm-{}", item.line));
+    let voice_model = find_voice(data, &request_id, item.speaker.as_deref())?;
+    let (speaker, emotion) = (voice_model.name.as_str(), DEFAULT_EMOTION);
+    let params = voice_model.manifest.infer_params();
+    let options = SynthesisOptions::default();
+
+    let fingerprint = data
+        .gpt_sovits
+        .fingerprint(speaker, emotion)
+        .map_err(|e| ApiError::from_infer(&request_id, e))?;
+    let cache_key = CacheKey::new(
+        fingerprint.clone(),
+        speaker,
+        emotion,
+        &item.text,
+        params,
+        options.clone(),
+    );
+    // 只查索引，不读文件
+    let cached = cache
+        .lock()
+        .map_err(|e| ApiError::internal(&request_id, format!("获取缓存锁失败: {}", e)))?
+        .contains(&cache_key);
+    if cached {
+        return Ok(false);
+    }
+
+    // 与普通请求共用推理队列，队列满时等待后重试，而不是算作失败
+    let mut retries = 0;
+    loop {
+        let (params, chunks) = prepare_inference(
+            &request_id,
+            data,
+            cache,
+            fingerprint.clone(),
+            speaker,
+            emotion,
+            &item.text,
+            params,
+            &options,
+        )?;
+        let result = infer_and_cache(
+            &request_id,
+            data,
+            cache,
+            cache_key.clone(),
+            speaker,
+            emotion,
+            &item.text,
+            params,
+            options.clone(),
+            chunks,
+        )
+        .await;
+        match result {
+            Err(ApiError {
+                retry_after: Some(secs),
+                ..
+            }) if retries < QUEUE_FULL_RETRIES => {
+                let wait = Duration::from_secs((secs << retries).min(60));
+                log::debug!(
+                    "推理队列已满，{} 秒后重试第 {} 行",
+                    wait.as_secs(),
+                    item.line
+                );
+                actix_web::rt::time::sleep(wait).await;
+                retries += 1;
+            }
+            result => return result.map(|_| true),
+        }
+    }
+}
+
+#[test]
+fn test_prewarm_load() {
+    let path = std::env::temp_dir().join(format!("gpt_sovits_prewarm_{}.txt", std::process::id()));
+    // 注释、空行和只有空白的行都跳过，行号仍按文件中的位置计
+    let content = "alice\t你好。\n\
+        # 注释\n\
+        \n\
+        \t  \n\
+        没有 TAB 的一行\n\
+        \t没有声音名\n\
+        bob\t  前后有空格  \n";
+    fs::write(&path, content).unwrap();
+    let prewarm = Prewarm::load(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let item = |line, speaker: Option<&str>, text: &str| PrewarmItem {
+        line,
+        speaker: speaker.map(String::from),
+        text: text.to_string(),
+    };
+    assert_eq!(
+        prewarm.items,
+        vec![
+            item(1, Some("alice"), "你好。"),
+            item(5, None, "没有 TAB 的一行"),
+            item(6, None, "没有声音名"),
+            item(7, Some("bob"), "前后有空格"),
+        ]
+    );
+    assert_eq!(prewarm.progress().to_json()["total"], 4);
+}
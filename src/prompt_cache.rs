@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::{Digest, Sha256};
+use tch::{Device, Tensor};
+
+use crate::{ModelKey, SharedTensor, SpeakerPrompt};
+
+/// Bumped whenever the stored tensors or the key change meaning, so files
+/// written by older versions are never read back.
+const FORMAT_VERSION: i64 = 1;
+
+const VERSION_TENSOR: &str = "format_version";
+const PROMPT_TENSORS: [&str; 4] = [
+    "ssl_content",
+    "ref_audio_32k",
+    "ref_phone_seq",
+    "ref_bert_seq",
+];
+
+impl SpeakerPrompt {
+    /// Writes the prompt tensors to a safetensors file. The reference text is
+    /// not stored, callers key the file by it.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let tensors = [
+            (VERSION_TENSOR, Tensor::from_slice(&[FORMAT_VERSION])),
+            (PROMPT_TENSORS[0], cpu(&self.ssl_content.0)),
+            (PROMPT_TENSORS[1], cpu(&self.ref_audio_32k.0)),
+            (PROMPT_TENSORS[2], cpu(&self.ref_phone_seq.0)),
+            (PROMPT_TENSORS[3], cpu(&self.ref_bert_seq.0)),
+        ];
+        Tensor::write_safetensors(&tensors, path)?;
+        Ok(())
+    }
+
+    /// Reads a prompt written by `save`, moving the tensors to `device`.
+    pub fn load(path: impl AsRef<Path>, ref_text: &str, device: Device) -> anyhow::Result<Self> {
+        let mut tensors: HashMap<String, Tensor> =
+            Tensor::read_safetensors(path)?.into_iter().collect();
+
+        let version = tensors
+            .get(VERSION_TENSOR)
+            .map(|version| version.int64_value(&[0]));
+        if version != Some(FORMAT_VERSION) {
+            return Err(anyhow::anyhow!(
+                "unsupported prompt format {:?}, expected {}",
+                version,
+                FORMAT_VERSION
+            ));
+        }
+
+        let mut take = |name: &str| {
+            tensors
+                .remove(name)
+                .map(|tensor| SharedTensor(tensor.to_device(device)))
+                .ok_or_else(|| anyhow::anyhow!("missing tensor {}", name))
+        };
+        let ssl_content = take(PROMPT_TENSORS[0])?;
+        let ref_audio_32k = take(PROMPT_TENSORS[1])?;
+        let ref_phone_seq = take(PROMPT_TENSORS[2])?;
+        let ref_bert_seq = take(PROMPT_TENSORS[3])?;
+        Ok(Self {
+            hash: Self::content_hash(ref_text, &ref_audio_32k.0)?,
+            ref_text: ref_text.to_string(),
+            ssl_content,
+            ref_audio_32k,
+            ref_phone_seq,
+            ref_bert_seq,
+        })
+    }
+}
+
+fn cpu(tensor: &Tensor) -> Tensor {
+    tensor.to_device(Device::Cpu).contiguous()
+}
+
+/// Directory of precomputed prompts, one safetensors file per reference clip
+/// and text.
+#[derive(Debug)]
+pub(crate) struct PromptCache {
+    dir: PathBuf,
+    /// Hash of the models that produce the prompt tensors.
+    models_hash: [u8; 32],
+}
+
+impl PromptCache {
+    /// `model_paths` are the SSL model and text frontend models, a prompt is
+    /// only reused with the models it was computed by.
+    pub(crate) fn new(dir: &str, model_paths: &[&str]) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("create prompt cache {}: {}", dir, e))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        for path in model_paths {
+            hasher.update(ModelKey::of(Path::new(path))?.0);
+        }
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            models_hash: hasher.finalize().into(),
+        })
+    }
+
+    pub(crate) fn key(&self, samples: &[f32], sample_rate: usize, ref_text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(FORMAT_VERSION.to_le_bytes());
+        hasher.update(self.models_hash);
+        hasher.update((sample_rate as u64).to_le_bytes());
+        hasher.update((samples.len() as u64).to_le_bytes());
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        hasher.update(bytes);
+        hasher.update(ref_text);
+        hex::encode(hasher.finalize())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.safetensors", key))
+    }
+
+    /// Returns the stored prompt, or None if there is none or it cannot be
+    /// read.
+    pub(crate) fn load(&self, key: &str, ref_text: &str, device: Device) -> Option<SpeakerPrompt> {
+        let path = self.path(key);
+        if !path.is_file() {
+            return None;
+        }
+        match SpeakerPrompt::load(&path, ref_text, device) {
+            Ok(prompt) => {
+                log::debug!("load prompt from {}", path.display());
+                Some(prompt)
+            }
+            Err(e) => {
+                log::warn!("ignore prompt cache {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Stores a prompt, written to a temporary file first so readers never see
+    /// a partial file. The temporary name is unique per process and call, as
+    /// several processes or speakers may save the same prompt at once.
+    pub(crate) fn save(&self, key: &str, prompt: &SpeakerPrompt) -> anyhow::Result<()> {
+        static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT_TMP.fetch_add(1, Ordering::Relaxed);
+        let path = self.path(key);
+        let tmp = self
+            .dir
+            .join(format!(".{}.{}.{}.tmp", key, std::process::id(), n));
+        let saved = prompt
+            .save(&tmp)
+            .and_then(|_| std::fs::rename(&tmp, &path).map_err(Into::into));
+        if saved.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        saved
+    }
+}
+
+#[test]
+fn test_prompt_cache_key() {
+    let cache = PromptCache {
+        dir: PathBuf::new(),
+        models_hash: [0; 32],
+    };
+    let samples = vec![0.1f32, 0.2, 0.3];
+    let key = cache.key(&samples, 32000, "你好.");
+
+    assert_eq!(key, cache.key(&samples, 32000, "你好."));
+    assert_ne!(key, cache.key(&samples, 16000, "你好."));
+    assert_ne!(key, cache.key(&samples, 32000, "你好。"));
+    assert_ne!(key, cache.key(&[0.1, 0.2, 0.4], 32000, "你好."));
+
+    let other_models = PromptCache {
+        dir: PathBuf::new(),
+        models_hash: [1; 32],
+    };
+    assert_ne!(key, other_models.key(&samples, 32000, "你好."));
+}
+
+#[test]
+fn test_prompt_cache_round_trip() {
+    let dir = std::env::temp_dir().join(format!("gpt_sovits_prompts_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cache = PromptCache {
+        dir: dir.clone(),
+        models_hash: [0; 32],
+    };
+
+    let ref_text = "你好.";
+    let ref_audio_32k = Tensor::from_slice(&[0.1f32, -0.2, 0.3]);
+    let prompt = SpeakerPrompt {
+        hash: SpeakerPrompt::content_hash(ref_text, &ref_audio_32k).unwrap(),
+        ref_text: ref_text.to_string(),
+        ssl_content: SharedTensor(Tensor::from_slice(&[1.0f32, 2.0])),
+        ref_audio_32k: SharedTensor(ref_audio_32k),
+        ref_phone_seq: SharedTensor(Tensor::from_slice(&[3i64, 1, 4])),
+        ref_bert_seq: SharedTensor(Tensor::from_slice(&[0.5f32])),
+    };
+    let key = cache.key(&[0.1, 0.2], 32000, ref_text);
+    assert!(cache.load(&key, ref_text, Device::Cpu).is_none());
+
+    cache.save(&key, &prompt).unwrap();
+    let loaded = cache.load(&key, ref_text, Device::Cpu).unwrap();
+    assert_eq!(loaded.hash, prompt.hash);
+    assert_eq!(loaded.ref_text, ref_text);
+    let values = |tensor: &SharedTensor| Vec::<f32>::try_from(&tensor.0).unwrap();
+    assert_eq!(values(&loaded.ssl_content), vec![1.0, 2.0]);
+    assert_eq!(values(&loaded.ref_audio_32k), vec![0.1, -0.2, 0.3]);
+    assert_eq!(
+        Vec::<i64>::try_from(&loaded.ref_phone_seq.0).unwrap(),
+        vec![3, 1, 4]
+    );
+    assert_eq!(values(&loaded.ref_bert_seq), vec![0.5]);
+
+    // no temporary file is left behind
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(files.len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,243 @@
+// 推理调度：固定数量的阻塞线程执行推理，请求在有界队列中排队，不占用 actix 的异步线程
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleError {
+    // 队列已满，客户端应稍后重试
+    QueueFull,
+    // 排队或推理超过了请求超时时间
+    Timeout,
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::QueueFull => write!(f, "inference queue is full"),
+            ScheduleError::Timeout => write!(f, "inference timed out"),
+        }
+    }
+}
+
+struct Job {
+    enqueued: Instant,
+    // run 在等待结果超时时自己计数，避免重复统计
+    count_timeout: bool,
+    // 调用方已经不再等待结果（超时或请求被取消），还没开始的任务直接跳过
+    abandoned: Option<Arc<AtomicBool>>,
+    // 参数为 Err(Timeout) 时表示排队已超时，任务只需要通知调用方
+    run: Box<dyn FnOnce(Result<(), ScheduleError>) + Send>,
+}
+
+#[derive(Default)]
+struct Stats {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+    total_wait_ms: AtomicU64,
+    last_wait_ms: AtomicU64,
+}
+
+pub struct Scheduler {
+    tx: SyncSender<Job>,
+    stats: Arc<Stats>,
+    workers: usize,
+    queue_depth: usize,
+    timeout: Duration,
+}
+
+impl Scheduler {
+    pub fn new(workers: usize, queue_depth: usize, timeout: Duration) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel::<Job>(queue_depth);
+        let rx = Arc::new(Mutex::new(rx));
+        let stats = Arc::new(Stats::default());
+
+        for i in 0..workers {
+            let rx = rx.clone();
+            let stats = stats.clone();
+            std::thread::Builder::new()
+                .name(format!("infer-worker-{}", i))
+                .spawn(move || loop {
+                    // 只在取任务时持有锁，推理时其他线程可以继续取任务
+                    let job = match rx.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
+
+                    let waited = job.enqueued.elapsed();
+                    let waited_ms = waited.as_millis() as u64;
+                    stats.total_wait_ms.fetch_add(waited_ms, Ordering::Relaxed);
+                    stats.last_wait_ms.store(waited_ms, Ordering::Relaxed);
+                    log::debug!("任务排队 {} ms", waited_ms);
+
+                    let abandoned = job
+                        .abandoned
+                        .as_ref()
+                        .is_some_and(|abandoned| abandoned.load(Ordering::Relaxed));
+                    if waited >= timeout || abandoned {
+                        if job.count_timeout {
+                            stats.timed_out.fetch_add(1, Ordering::Relaxed);
+                        }
+                        (job.run)(Err(ScheduleError::Timeout));
+                        continue;
+                    }
+
+                    stats.running.fetch_add(1, Ordering::Relaxed);
+                    (job.run)(Ok(()));
+                    stats.running.fetch_sub(1, Ordering::Relaxed);
+                    stats.completed.fetch_add(1, Ordering::Relaxed);
+                })?;
+        }
+
+        Ok(Scheduler {
+            tx,
+            stats,
+            workers,
+            queue_depth,
+            timeout,
+        })
+    }
+
+    // 放入队列后立即返回，任务开始前已超时则以 Err(Timeout) 调用，用于流式返回
+    pub fn spawn(
+        &self,
+        run: impl FnOnce(Result<(), ScheduleError>) + Send + 'static,
+    ) -> Result<(), ScheduleError> {
+        self.enqueue(Job {
+            enqueued: Instant::now(),
+            count_timeout: true,
+            abandoned: None,
+            run: Box::new(run),
+        })
+    }
+
+    fn enqueue(&self, job: Job) -> Result<(), ScheduleError> {
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.stats.queued.fetch_sub(1, Ordering::Relaxed);
+                self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(ScheduleError::QueueFull)
+            }
+        }
+    }
+
+    // 排队加推理的总时间超过 timeout 时返回 Timeout。还在排队的任务不会再执行，
+    // 已经开始的推理无法中断，会继续占用工作线程直到完成，结果丢弃
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, ScheduleError> {
+        // 返回或 future 被丢弃时标记任务已放弃
+        struct Abandon(Arc<AtomicBool>);
+        impl Drop for Abandon {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+        let abandon = Abandon(Arc::new(AtomicBool::new(false)));
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.enqueue(Job {
+            enqueued: Instant::now(),
+            count_timeout: false,
+            abandoned: Some(abandon.0.clone()),
+            run: Box::new(move |ready| {
+                let _ = tx.send(ready.map(|()| f()));
+            }),
+        })?;
+
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) | Err(_) => {
+                self.stats.timed_out.fetch_add(1, Ordering::Relaxed);
+                Err(ScheduleError::Timeout)
+            }
+        }
+    }
+
+    fn avg_wait_ms(&self) -> u64 {
+        let completed = self.stats.completed.load(Ordering::Relaxed)
+            + self.stats.timed_out.load(Ordering::Relaxed);
+        self.stats.total_wait_ms.load(Ordering::Relaxed) / completed.max(1)
+    }
+
+    // 按平均排队时间估计客户端应等待的秒数
+    pub fn retry_after_secs(&self) -> u64 {
+        self.avg_wait_ms().div_ceil(1000).clamp(1, 60)
+    }
+
+    pub fn status(&self) -> Value {
+        let stats = &self.stats;
+        json!({
+            "workers": self.workers,
+            "queue_depth": self.queue_depth,
+            "timeout_secs": self.timeout.as_secs(),
+            "queued": stats.queued.load(Ordering::Relaxed),
+            "running": stats.running.load(Ordering::Relaxed),
+            "completed": stats.completed.load(Ordering::Relaxed),
+            "rejected": stats.rejected.load(Ordering::Relaxed),
+            "timed_out": stats.timed_out.load(Ordering::Relaxed),
+            "avg_wait_ms": self.avg_wait_ms(),
+            "last_wait_ms": stats.last_wait_ms.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+fn block_worker(scheduler: &Scheduler) -> mpsc::Sender<()> {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    scheduler
+        .spawn(move |_| {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+    started_rx.recv().unwrap();
+    release_tx
+}
+
+#[test]
+fn test_queue_full() {
+    let scheduler = Scheduler::new(1, 1, Duration::from_secs(10)).unwrap();
+    let release = block_worker(&scheduler);
+
+    let (done_tx, done_rx) = mpsc::channel();
+    scheduler
+        .spawn(move |ready| done_tx.send(ready).unwrap())
+        .unwrap();
+    assert_eq!(scheduler.spawn(|_| {}), Err(ScheduleError::QueueFull));
+    assert_eq!(scheduler.status()["rejected"], 1);
+
+    drop(release);
+    assert_eq!(done_rx.recv().unwrap(), Ok(()));
+    assert!(scheduler.spawn(|_| {}).is_ok());
+}
+
+#[actix_web::test]
+async fn test_timeout() {
+    let scheduler = Scheduler::new(1, 4, Duration::from_millis(100)).unwrap();
+    let release = block_worker(&scheduler);
+
+    // 排队超时的任务返回 Timeout，之后也不会再执行
+    let ran = Arc::new(AtomicBool::new(false));
+    let ran_in_job = ran.clone();
+    let result = scheduler
+        .run(move || ran_in_job.store(true, Ordering::Relaxed))
+        .await;
+    assert_eq!(result, Err(ScheduleError::Timeout));
+
+    drop(release);
+    assert_eq!(scheduler.run(|| 42).await, Ok(42));
+    assert!(!ran.load(Ordering::Relaxed));
+    assert_eq!(scheduler.status()["timed_out"], 1);
+}
@@ -0,0 +1,474 @@
+use tch::Tensor;
+
+use crate::audio::SAMPLE_RATE;
+use crate::text::segment::{Segmenter, CLOSING, SENTENCE_END};
+use crate::{GPTSovits, InferError, InferParams};
+
+/// How long text is split before inference. The model degrades on long
+/// inputs, so every chunk is synthesized on its own.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum ChunkStrategy {
+    /// The whole text in one pass.
+    Whole,
+    /// Sentences, see `Segmenter`.
+    Sentences(Segmenter),
+    /// Chunks of at most this many characters, split at sentence, then word
+    /// boundaries.
+    MaxChars(usize),
+}
+
+impl Default for ChunkStrategy {
+    fn default() -> Self {
+        ChunkStrategy::Sentences(Segmenter::default())
+    }
+}
+
+impl ChunkStrategy {
+    /// Splits `text`, dropping chunks with nothing to pronounce such as a lone
+    /// `。`.
+    pub fn split(&self, text: &str) -> Vec<String> {
+        let chunks: Vec<&str> = match self {
+            ChunkStrategy::Whole => vec![text],
+            ChunkStrategy::Sentences(segmenter) => segmenter.split(text),
+            ChunkStrategy::MaxChars(max_chars) => text_splitter::TextSplitter::new(*max_chars)
+                .chunks(text)
+                .collect(),
+        };
+        chunks
+            .into_iter()
+            .filter(|chunk| chunk.chars().any(char::is_alphanumeric))
+            .map(|chunk| chunk.to_string())
+            .collect()
+    }
+}
+
+/// How `GPTSovits::infer_long` splits text and joins the audio of the chunks.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SynthesisOptions {
+    pub chunking: ChunkStrategy,
+    /// Pause after a chunk that ends a sentence (`。！？.!?；;…`), in
+    /// milliseconds at speed 1.0, shorter or longer at other speeds.
+    pub sentence_pause_ms: u32,
+    /// Pause after any other chunk, e.g. one ending with `，`.
+    pub clause_pause_ms: u32,
+    /// Cut leading and trailing silence the model produced, so pauses only
+    /// come from the two settings above.
+    pub trim_silence: bool,
+    /// Fade in and out at the edges of every chunk, avoids clicks where
+    /// speech meets the pause.
+    pub fade_ms: u32,
+    /// Adjacent pieces (chunks and pauses) overlap by this many
+    /// milliseconds with a linear crossfade, 0 joins them back to back.
+    pub crossfade_ms: u32,
+}
+
+impl Default for SynthesisOptions {
+    fn default() -> Self {
+        Self {
+            chunking: ChunkStrategy::default(),
+            sentence_pause_ms: 300,
+            clause_pause_ms: 150,
+            trim_silence: true,
+            fade_ms: 10,
+            crossfade_ms: 0,
+        }
+    }
+}
+
+/// Longest pause or fade accepted by `SynthesisOptions::validate`.
+pub const MAX_PAUSE_MS: u32 = 5000;
+
+impl SynthesisOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, ms) in [
+            ("sentence_pause_ms", self.sentence_pause_ms),
+            ("clause_pause_ms", self.clause_pause_ms),
+            ("fade_ms", self.fade_ms),
+            ("crossfade_ms", self.crossfade_ms),
+        ] {
+            if ms > MAX_PAUSE_MS {
+                return Err(anyhow::anyhow!(
+                    "{} must be <= {}, got {}",
+                    name,
+                    MAX_PAUSE_MS,
+                    ms
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Pause after a chunk with this text.
+    fn pause_after(&self, text: &str) -> u32 {
+        let last = text
+            .trim_end()
+            .trim_end_matches(|c| CLOSING.contains(&c))
+            .chars()
+            .next_back();
+        match last {
+            Some(c) if SENTENCE_END.contains(&c) => self.sentence_pause_ms,
+            _ => self.clause_pause_ms,
+        }
+    }
+}
+
+fn ms_to_samples(ms: u32) -> usize {
+    ms as usize * SAMPLE_RATE as usize / 1000
+}
+
+/// Amplitude below which trailing and leading audio counts as silence,
+/// about -40 dBFS.
+const SILENCE_THRESHOLD: f32 = 0.01;
+
+/// Cuts silence at both ends of `samples`, keeping a few milliseconds before
+/// and after the speech so soft onsets are not clipped.
+pub fn trim_silence(samples: &[f32]) -> &[f32] {
+    let margin = ms_to_samples(20);
+    let loud = |s: &f32| s.abs() > SILENCE_THRESHOLD;
+    match (
+        samples.iter().position(loud),
+        samples.iter().rposition(loud),
+    ) {
+        (Some(first), Some(last)) => {
+            &samples[first.saturating_sub(margin)..(last + 1 + margin).min(samples.len())]
+        }
+        _ => &[],
+    }
+}
+
+/// Linear fade in over the first and fade out over the last `len` samples.
+fn fade_edges(samples: &mut [f32], len: usize) {
+    let len = len.min(samples.len() / 2);
+    let total = samples.len();
+    for i in 0..len {
+        let gain = i as f32 / len as f32;
+        samples[i] *= gain;
+        samples[total - 1 - i] *= gain;
+    }
+}
+
+/// Joins chunk audio incrementally, so streamed output matches `infer_long`.
+/// The end of the last chunk is held back until the next one arrives to
+/// crossfade them.
+#[derive(Debug, Clone)]
+pub struct ChunkJoiner {
+    options: SynthesisOptions,
+    crossfade: usize,
+    /// pauses are divided by it to keep pace with the speech
+    speed: f32,
+    tail: Vec<f32>,
+    /// pause owed after the previous chunk, inserted once the next one comes
+    pause: Option<usize>,
+}
+
+impl ChunkJoiner {
+    pub fn new(options: &SynthesisOptions) -> Self {
+        Self {
+            options: options.clone(),
+            crossfade: ms_to_samples(options.crossfade_ms),
+            speed: 1.0,
+            tail: vec![],
+            pause: None,
+        }
+    }
+
+    /// Scales the pauses between chunks for audio synthesized at `speed`.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Adds the audio of the next chunk and the text it was synthesized
+    /// from, returns the samples that are final.
+    pub fn push(&mut self, text: &str, chunk: &[f32]) -> Vec<f32> {
+        let mut chunk = if self.options.trim_silence {
+            trim_silence(chunk).to_vec()
+        } else {
+            chunk.to_vec()
+        };
+        fade_edges(&mut chunk, ms_to_samples(self.options.fade_ms));
+
+        let mut output = vec![];
+        if let Some(pause) = self.pause.take().filter(|pause| *pause > 0) {
+            output = self.push_piece(&vec![0f32; pause]);
+        }
+        output.extend(self.push_piece(&chunk));
+        let pause = ms_to_samples(self.options.pause_after(text)) as f32 / self.speed;
+        self.pause = Some(pause.round() as usize);
+        output
+    }
+
+    /// Returns the samples held back for the crossfade.
+    pub fn finish(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.tail)
+    }
+
+    fn push_piece(&mut self, piece: &[f32]) -> Vec<f32> {
+        let overlap = self.tail.len().min(piece.len());
+        let mut joined = std::mem::take(&mut self.tail);
+        let start = joined.len() - overlap;
+        for (i, (out, sample)) in joined[start..].iter_mut().zip(piece).enumerate() {
+            let fade_in = (i as f32 + 0.5) / overlap as f32;
+            *out = *out * (1.0 - fade_in) + sample * fade_in;
+        }
+        joined.extend_from_slice(&piece[overlap..]);
+
+        let hold = self.crossfade.min(joined.len());
+        self.tail = joined.split_off(joined.len() - hold);
+        joined
+    }
+}
+
+/// Stores the audio of single chunks, so texts that share sentences only
+/// infer the new ones. Keys must include the speaker, prompt and
+/// `InferParams`, the chunk text is all `infer_long_streaming_cached` passes.
+pub trait ChunkCache {
+    /// Raw audio of `chunk` as inferred, before trimming and fades.
+    fn load(&mut self, chunk: &str) -> Option<Vec<f32>>;
+    fn save(&mut self, chunk: &str, samples: &[f32]);
+}
+
+/// Caches nothing.
+pub struct NoChunkCache;
+
+impl ChunkCache for NoChunkCache {
+    fn load(&mut self, _chunk: &str) -> Option<Vec<f32>> {
+        None
+    }
+
+    fn save(&mut self, _chunk: &str, _samples: &[f32]) {}
+}
+
+/// Copies a 1-D audio tensor returned by `GPTSovits::infer` into samples.
+pub fn tensor_to_samples(audio: &Tensor) -> anyhow::Result<Vec<f32>> {
+    let audio_size = audio.size1()? as usize;
+    let mut samples = vec![0f32; audio_size];
+    audio.f_copy_data(&mut samples, audio_size)?;
+    Ok(samples)
+}
+
+impl GPTSovits {
+    /// Synthesizes text of any length: splits it into chunks, infers them one
+    /// by one and joins the audio. Returns 32 kHz mono samples.
+    pub fn infer_long(
+        &self,
+        speaker: &str,
+        prompt: &str,
+        text: &str,
+        params: &InferParams,
+        options: &SynthesisOptions,
+    ) -> anyhow::Result<Vec<f32>> {
+        let mut samples = vec![];
+        self.infer_long_streaming(speaker, prompt, text, params, options, |audio| {
+            samples.extend(audio);
+            true
+        })?;
+        Ok(samples)
+    }
+
+    /// Like `infer_long`, but hands the joined audio to `on_audio` as soon as
+    /// each chunk is ready. Stops early when `on_audio` returns false.
+    pub fn infer_long_streaming(
+        &self,
+        speaker: &str,
+        prompt: &str,
+        text: &str,
+        params: &InferParams,
+        options: &SynthesisOptions,
+        on_audio: impl FnMut(Vec<f32>) -> bool,
+    ) -> anyhow::Result<()> {
+        let cache = &mut NoChunkCache;
+        self.infer_long_streaming_cached(speaker, prompt, text, params, options, cache, on_audio)
+    }
+
+    /// Like `infer_long_streaming`, but takes chunks found in `cache` instead
+    /// of inferring them, and saves the ones it infers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn infer_long_streaming_cached(
+        &self,
+        speaker: &str,
+        prompt: &str,
+        text: &str,
+        params: &InferParams,
+        options: &SynthesisOptions,
+        cache: &mut dyn ChunkCache,
+        on_audio: impl FnMut(Vec<f32>) -> bool,
+    ) -> anyhow::Result<()> {
+        let infer = |chunk: &str| {
+            let audio = self.infer_with_prompt(speaker, prompt, chunk, params)?;
+            tensor_to_samples(&audio)
+        };
+        synthesize_chunks(text, params.speed, options, cache, infer, on_audio)
+    }
+}
+
+/// The chunk loop of `infer_long_streaming_cached`, with `infer` turning one
+/// chunk of text into samples.
+fn synthesize_chunks(
+    text: &str,
+    speed: f32,
+    options: &SynthesisOptions,
+    cache: &mut dyn ChunkCache,
+    mut infer: impl FnMut(&str) -> anyhow::Result<Vec<f32>>,
+    mut on_audio: impl FnMut(Vec<f32>) -> bool,
+) -> anyhow::Result<()> {
+    let chunks = options.chunking.split(text);
+    if chunks.is_empty() {
+        let reason = format!("{text} has nothing to synthesize");
+        return Err(InferError::InvalidText(reason).into());
+    }
+
+    let mut joiner = ChunkJoiner::new(options).with_speed(speed);
+    for chunk in &chunks {
+        let audio = match cache.load(chunk) {
+            Some(audio) => {
+                log::debug!("cached chunk: {}", chunk);
+                audio
+            }
+            None => {
+                log::debug!("infer chunk: {}", chunk);
+                let audio = infer(chunk)?;
+                cache.save(chunk, &audio);
+                audio
+            }
+        };
+        let samples = joiner.push(chunk, &audio);
+        if !samples.is_empty() && !on_audio(samples) {
+            return Ok(());
+        }
+    }
+    let samples = joiner.finish();
+    if !samples.is_empty() {
+        on_audio(samples);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_chunk_joiner() {
+    let options = SynthesisOptions {
+        sentence_pause_ms: 2,
+        clause_pause_ms: 1,
+        trim_silence: false,
+        fade_ms: 0,
+        ..Default::default()
+    };
+    let mut joiner = ChunkJoiner::new(&options);
+    let mut output = joiner.push("你好，", &[1.0; 10]);
+    output.extend(joiner.push("世界。”", &[1.0; 10]));
+    output.extend(joiner.push("再见。", &[1.0; 10]));
+    output.extend(joiner.finish());
+    // 1 ms after the comma, 2 ms after the full stop, at 32 kHz
+    assert_eq!(output.len(), 10 + 32 + 10 + 64 + 10);
+    assert_eq!(output[10..42], [0.0; 32]);
+    assert_eq!(output[52..116], [0.0; 64]);
+
+    // pauses shrink with faster speech
+    let mut joiner = ChunkJoiner::new(&options).with_speed(2.0);
+    let mut output = joiner.push("世界。", &[1.0; 10]);
+    output.extend(joiner.push("再见。", &[1.0; 10]));
+    output.extend(joiner.finish());
+    assert_eq!(output.len(), 10 + 32 + 10);
+
+    let options = SynthesisOptions {
+        sentence_pause_ms: 0,
+        clause_pause_ms: 0,
+        trim_silence: false,
+        fade_ms: 0,
+        crossfade_ms: 1,
+        ..Default::default()
+    };
+    let mut joiner = ChunkJoiner::new(&options);
+    let mut output = joiner.push("你好。", &[1.0; 100]);
+    output.extend(joiner.push("你好。", &[1.0; 100]));
+    output.extend(joiner.finish());
+    assert_eq!(output.len(), 200 - 32);
+    assert!(output.iter().all(|s| (s - 1.0).abs() < 1e-6));
+}
+
+#[test]
+fn test_trim_silence() {
+    let mut samples = vec![0.0; 3200];
+    samples[1600] = 0.5;
+    // 20 ms margin on both sides
+    assert_eq!(trim_silence(&samples).len(), 640 + 1 + 640);
+    assert!(trim_silence(&[0.0; 100]).is_empty());
+}
+
+#[test]
+fn test_chunk_strategy() {
+    assert_eq!(ChunkStrategy::Whole.split("你好。"), vec!["你好。"]);
+    assert!(ChunkStrategy::Whole.split("。").is_empty());
+    assert_eq!(
+        ChunkStrategy::default().split("今天天气很好。我们去公园散步吧！"),
+        vec!["今天天气很好。", "我们去公园散步吧！"]
+    );
+}
+
+#[test]
+fn test_synthesize_chunks_cached() {
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MapCache {
+        chunks: HashMap<String, Vec<f32>>,
+        saves: usize,
+    }
+
+    impl ChunkCache for MapCache {
+        fn load(&mut self, chunk: &str) -> Option<Vec<f32>> {
+            self.chunks.get(chunk).cloned()
+        }
+
+        fn save(&mut self, chunk: &str, samples: &[f32]) {
+            self.saves += 1;
+            self.chunks.insert(chunk.to_string(), samples.to_vec());
+        }
+    }
+
+    let options = SynthesisOptions {
+        trim_silence: false,
+        fade_ms: 0,
+        crossfade_ms: 0,
+        ..Default::default()
+    };
+    let mut cache = MapCache::default();
+    let run = |text: &str, cache: &mut MapCache| {
+        let mut inferred = vec![];
+        let mut samples = vec![];
+        let infer = |chunk: &str| {
+            inferred.push(chunk.to_string());
+            Ok(vec![0.5; chunk.chars().count() * 100])
+        };
+        synthesize_chunks(text, 1.0, &options, cache, infer, |audio| {
+            samples.extend(audio);
+            true
+        })
+        .unwrap();
+        (inferred, samples)
+    };
+
+    let (inferred, first) = run("今天天气很好。我们去公园散步吧！", &mut cache);
+    assert_eq!(inferred, vec!["今天天气很好。", "我们去公园散步吧！"]);
+    assert_eq!(cache.saves, 2);
+
+    // only the new sentence is inferred, the shared one comes from the cache
+    let (inferred, _) = run("今天天气很好。你觉得怎么样？", &mut cache);
+    assert_eq!(inferred, vec!["你觉得怎么样？"]);
+    assert_eq!(cache.saves, 3);
+
+    // fully cached text joins to the same audio without inferring
+    let (inferred, again) = run("今天天气很好。我们去公园散步吧！", &mut cache);
+    assert!(inferred.is_empty());
+    assert_eq!(again, first);
+
+    let result = synthesize_chunks(
+        "。",
+        1.0,
+        &options,
+        &mut cache,
+        |_| unreachable!(),
+        |_| true,
+    );
+    assert!(result.is_err());
+}
@@ -8,12 +8,13 @@ use pest::Parser;
 use tch::{Kind, Tensor};
 use tokenizers::Tokenizer;
 
-use crate::GPTSovits;
+use crate::{GPTSovits, InferError};
 
 pub mod g2pw;
 
 pub mod dict;
 pub mod num;
+pub mod segment;
 
 #[inline]
 fn get_phone_symbol(symbols: &HashMap<String, i64>, ph: &str) -> i64 {
@@ -390,7 +391,10 @@ pub fn get_phone_and_bert(gpts: &GPTSovits, text: &str) -> anyhow::Result<(Tenso
                 bert_seq.push(bert);
             }
             Sentence::Num(num) => {
-                for s in num.to_phone_sentence()? {
+                let num_sentences = num
+                    .to_phone_sentence()
+                    .map_err(|e| InferError::InvalidText(format!("{}: {}", num.num_text, e)))?;
+                for s in num_sentences {
                     log::trace!("num text: {:?}", num.num_text);
                     match s {
                         Sentence::Zh(mut zh) => {
@@ -417,7 +421,7 @@ pub fn get_phone_and_bert(gpts: &GPTSovits, text: &str) -> anyhow::Result<(Tenso
     }
 
     if phone_seq.is_empty() {
-        return Err(anyhow::anyhow!("{text} get phone_seq is empty"));
+        return Err(InferError::InvalidText(format!("{text} get phone_seq is empty")).into());
     }
     if bert_seq.is_empty() {
         return Err(anyhow::anyhow!("{text} get bert_seq is empty"));
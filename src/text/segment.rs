@@ -0,0 +1,319 @@
+use pest::Parser;
+
+use super::num::{ExprParser, Rule};
+
+/// Ends a sentence, also used to pick the pause after a chunk.
+pub(crate) const SENTENCE_END: [char; 9] = ['。', '！', '？', '.', '!', '?', '；', ';', '…'];
+/// Secondary split points, only used inside overlong sentences.
+const COMMAS: [char; 2] = ['，', ','];
+/// Kept with the sentence they close, e.g. `他说：“好。”` ends after `”`.
+pub(crate) const CLOSING: [char; 10] = ['”', '’', '"', '\'', '）', ')', '」', '』', '》', '】'];
+
+/// Splits text into sentences at sentence-final punctuation.
+///
+/// Fragments shorter than `min_chars` are merged with a neighbour, sentences
+/// longer than `max_chars` are split at commas, then at spaces or between
+/// characters if a piece is still too long. A span that `num::ExprParser`
+/// reads as one number expression or identifier, such as `1,000.5`,
+/// `3.14%` or `GPT-4.5`, is never split.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Segmenter {
+    pub min_chars: usize,
+    pub max_chars: usize,
+}
+
+impl Default for Segmenter {
+    fn default() -> Self {
+        Self {
+            min_chars: 5,
+            max_chars: 50,
+        }
+    }
+}
+
+impl Segmenter {
+    pub fn split<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let protected = protected_spans(text);
+
+        let mut sentences = vec![];
+        for (start, end) in split_at(text, &protected, 0, text.len(), &SENTENCE_END) {
+            if char_count(&text[start..end]) > self.max_chars {
+                let pieces: Vec<(usize, usize)> = split_at(text, &protected, start, end, &COMMAS)
+                    .into_iter()
+                    .flat_map(|(start, end)| {
+                        split_long(text, &protected, start, end, self.max_chars)
+                    })
+                    .collect();
+                sentences.extend(pack(text, &pieces, self.max_chars));
+            } else {
+                sentences.push((start, end));
+            }
+        }
+
+        merge_short(text, sentences, self.min_chars, self.max_chars)
+            .into_iter()
+            .map(|(start, end)| text[start..end].trim())
+            .filter(|sentence| !sentence.is_empty())
+            .collect()
+    }
+}
+
+fn char_count(s: &str) -> usize {
+    s.trim().chars().count()
+}
+
+fn is_word_start(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ('α'..='ω').contains(&c) || ('Α'..='Ω').contains(&c)
+}
+
+/// Byte ranges that the number and identifier grammar parses as one token.
+fn protected_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut pos = 0;
+    while let Some(c) = text[pos..].chars().next() {
+        if !is_word_start(c) {
+            pos += c.len_utf8();
+            continue;
+        }
+        let rest = &text[pos..];
+        let len = [Rule::ident, Rule::signs]
+            .into_iter()
+            .filter_map(|rule| ExprParser::parse(rule, rest).ok()?.next())
+            .map(|pair| pair.as_str().len())
+            .max()
+            .unwrap_or(0)
+            .max(c.len_utf8());
+        spans.push((pos, pos + len));
+        pos += len;
+    }
+    spans
+}
+
+fn is_protected(protected: &[(usize, usize)], pos: usize) -> bool {
+    protected
+        .iter()
+        .any(|(start, end)| *start <= pos && pos < *end)
+}
+
+/// Splits `text[start..end]` after each of `marks`, keeping repeated marks and
+/// closing quotes with the piece they end.
+fn split_at(
+    text: &str,
+    protected: &[(usize, usize)],
+    start: usize,
+    end: usize,
+    marks: &[char],
+) -> Vec<(usize, usize)> {
+    let mut pieces = vec![];
+    let mut piece_start = start;
+    let mut chars = text[start..end].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let pos = start + i;
+        if !marks.contains(&c) || is_protected(protected, pos) {
+            continue;
+        }
+        // `1,000` is one number even though the grammar has no thousands separator
+        let prev = text[..pos].chars().next_back();
+        let next = chars.peek().map(|(_, c)| *c);
+        if c == ','
+            && prev.is_some_and(|c| c.is_ascii_digit())
+            && next.is_some_and(|c| c.is_ascii_digit())
+        {
+            continue;
+        }
+
+        let mut piece_end = pos + c.len_utf8();
+        while let Some((i, c)) = chars.peek().copied() {
+            if marks.contains(&c) || CLOSING.contains(&c) {
+                piece_end = start + i + c.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        pieces.push((piece_start, piece_end));
+        piece_start = piece_end;
+    }
+    if piece_start < end {
+        pieces.push((piece_start, end));
+    }
+    pieces
+}
+
+/// Splits `text[start..end]` into pieces of at most `max_chars`, breaking
+/// after the last space that fits, or else after the last character that
+/// fits. Never breaks inside a protected span, a span longer than `max_chars`
+/// stays whole.
+fn split_long(
+    text: &str,
+    protected: &[(usize, usize)],
+    start: usize,
+    end: usize,
+    max_chars: usize,
+) -> Vec<(usize, usize)> {
+    // not inside a protected span, a word or a number such as `1,000`
+    let can_break = |pos: usize| {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == ',' || c == '.';
+        let in_word = text[..pos].chars().next_back().is_some_and(is_word)
+            && text[pos..].chars().next().is_some_and(is_word);
+        !in_word && !protected.iter().any(|(s, e)| *s < pos && pos < *e)
+    };
+
+    let max_chars = max_chars.max(1);
+    let mut pieces = vec![];
+    let mut piece_start = start;
+    while char_count(&text[piece_start..end]) > max_chars {
+        let piece = &text[piece_start..end];
+        let skipped = piece.len() - piece.trim_start().len();
+        let limit = piece_start
+            + skipped
+            + piece[skipped..]
+                .char_indices()
+                .nth(max_chars)
+                .map_or(piece.len() - skipped, |(i, _)| i);
+        let breaks: Vec<usize> = text[piece_start..limit]
+            .char_indices()
+            .map(|(i, c)| piece_start + i + c.len_utf8())
+            .filter(|pos| *pos > piece_start + skipped && can_break(*pos))
+            .collect();
+        let after_space = breaks
+            .iter()
+            .rev()
+            .find(|pos| text[..**pos].ends_with(char::is_whitespace));
+        let piece_end = match after_space.or(breaks.last()) {
+            Some(pos) => *pos,
+            // the piece starts with a protected span longer than max_chars
+            None => protected
+                .iter()
+                .find(|(s, e)| *s < limit && limit < *e)
+                .map_or(limit, |(_, e)| *e),
+        };
+        pieces.push((piece_start, piece_end));
+        piece_start = piece_end;
+    }
+    pieces.push((piece_start, end));
+    pieces
+}
+
+/// Joins consecutive pieces as long as they fit in `max_chars`.
+fn pack(text: &str, pieces: &[(usize, usize)], max_chars: usize) -> Vec<(usize, usize)> {
+    let mut packed: Vec<(usize, usize)> = vec![];
+    for &(start, end) in pieces {
+        match packed.last_mut() {
+            Some(last) if char_count(&text[last.0..end]) <= max_chars => last.1 = end,
+            _ => packed.push((start, end)),
+        }
+    }
+    packed
+}
+
+/// Merges sentences shorter than `min_chars` into the next one, or into the
+/// previous one if the next one is too long for it or at the end of the text.
+/// A fragment that fits in neither stays on its own.
+fn merge_short(
+    text: &str,
+    sentences: Vec<(usize, usize)>,
+    min_chars: usize,
+    max_chars: usize,
+) -> Vec<(usize, usize)> {
+    let mut merged: Vec<(usize, usize)> = vec![];
+    let mut pending: Option<(usize, usize)> = None;
+    let merge_back =
+        |merged: &mut Vec<(usize, usize)>, (start, end): (usize, usize)| match merged.last_mut() {
+            Some(last) if char_count(&text[last.0..end]) <= max_chars => last.1 = end,
+            _ => merged.push((start, end)),
+        };
+    for (start, end) in sentences {
+        let start = match pending.take() {
+            Some((short_start, _)) if char_count(&text[short_start..end]) <= max_chars => {
+                short_start
+            }
+            Some(short) => {
+                merge_back(&mut merged, short);
+                start
+            }
+            None => start,
+        };
+        if char_count(&text[start..end]) < min_chars {
+            pending = Some((start, end));
+        } else {
+            merged.push((start, end));
+        }
+    }
+    if let Some((start, _)) = pending {
+        merge_back(&mut merged, (start, text.len()));
+    }
+    merged
+}
+
+#[test]
+fn test_segmenter() {
+    let segmenter = Segmenter::default();
+
+    assert_eq!(
+        segmenter.split("今天天气很好。我们去公园散步吧！你觉得怎么样？"),
+        vec!["今天天气很好。", "我们去公园散步吧！", "你觉得怎么样？"]
+    );
+    // numbers and identifiers stay whole
+    assert_eq!(
+        segmenter.split("总价是1,000.5元，增长了3.5%。GPT-4.5发布了。"),
+        vec!["总价是1,000.5元，增长了3.5%。", "GPT-4.5发布了。"]
+    );
+    assert_eq!(
+        segmenter.split("我想了很久……还是算了；明天再说吧。"),
+        vec!["我想了很久……", "还是算了；", "明天再说吧。"]
+    );
+    // closing quotes stay with their sentence, short fragments are merged
+    assert_eq!(
+        segmenter.split("他说：“好的。”嗯。然后我们就出发了。"),
+        vec!["他说：“好的。”", "嗯。然后我们就出发了。"]
+    );
+
+    let segmenter = Segmenter {
+        min_chars: 2,
+        max_chars: 10,
+    };
+    assert_eq!(
+        segmenter.split("第一部分比较长，第二部分也比较长，第三部分。"),
+        vec!["第一部分比较长，", "第二部分也比较长，", "第三部分。"]
+    );
+}
+
+#[test]
+fn test_segmenter_max_chars() {
+    let segmenter = Segmenter {
+        min_chars: 5,
+        max_chars: 10,
+    };
+    // a short fragment is not merged forward past max_chars, it goes back
+    assert_eq!(
+        segmenter.split("前面这一句话。好。后面这一句话挺长的。"),
+        vec!["前面这一句话。好。", "后面这一句话挺长的。"]
+    );
+    // with no room on either side it stays alone
+    assert_eq!(
+        segmenter.split("第一句话有九个字。嗯。第二句话有九个字。"),
+        vec!["第一句话有九个字。", "嗯。", "第二句话有九个字。"]
+    );
+
+    // no commas: spaces first, then characters, numbers stay whole
+    let segmenter = Segmenter {
+        min_chars: 1,
+        max_chars: 12,
+    };
+    assert_eq!(
+        segmenter.split("the quick brown fox jumps over the lazy dog"),
+        vec!["the quick", "brown fox", "jumps over", "the lazy dog"]
+    );
+    assert_eq!(
+        segmenter.split("这是一个没有任何标点的很长很长的句子价格是1,234.56元整"),
+        vec![
+            "这是一个没有任何标点的很",
+            "长很长的句子价格是",
+            "1,234.56元整"
+        ]
+    );
+    let sentences = segmenter.split("一二三四五六七八九十一二三四五六七八九十一二三四五");
+    assert_eq!(sentences.len(), 3);
+    assert!(sentences.iter().all(|s| s.chars().count() <= 12));
+}
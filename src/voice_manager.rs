@@ -1,17 +1,336 @@
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::{GPTSovits, InferParams, DEFAULT_PROMPT};
+
+/// Files every voice directory holds.
+pub const REF_AUDIO_FILE: &str = "ref.wav";
+pub const REF_TEXT_FILE: &str = "ref.txt";
+pub const MODEL_FILE: &str = "gpt_sovits_model.pt";
+/// Optional metadata, see `VoiceManifest`.
+pub const MANIFEST_FILE: &str = "voice.toml";
+
+/// Emotion backed by `ref.wav` and `ref.txt`, which every voice has. Each
+/// emotion is a prompt of the voice's speaker.
+pub const DEFAULT_EMOTION: &str = DEFAULT_PROMPT;
+
+/// Reference clips outside this range give poor results, as in upstream
+/// GPT-SoVITS.
+pub const MIN_REF_AUDIO_SECS: f32 = 3.0;
+pub const MAX_REF_AUDIO_SECS: f32 = 10.0;
+/// The SSL model works at 16 kHz, lower rates lose too much.
+pub const MIN_REF_SAMPLE_RATE: u32 = 16000;
+
+/// Reference clip and text of a voice that passed validation.
+#[derive(Debug, Clone)]
+pub struct VoiceRef {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub text: String,
+}
+
+/// Load state of a voice directory, for reporting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceStatus {
+    pub name: String,
+    pub loaded: bool,
+    /// Why the voice was skipped, or why the last reload failed while the
+    /// previous version stays loaded.
+    pub error: Option<String>,
+}
+
+/// Optional `voice.toml` in a voice directory:
+///
+/// ```toml
+/// display_name = "小明"
+/// language = "zh"
+/// gender = "male"
+/// description = "young, calm narrator"
+///
+/// [params]
+/// top_k = 10
+/// temperature = 0.8
+/// speed = 1.1
+///
+/// [emotions.happy]
+/// ref_audio = "happy.wav"
+/// ref_text = "happy.txt"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VoiceManifest {
+    pub display_name: Option<String>,
+    pub language: Option<String>,
+    pub gender: Option<String>,
+    pub description: Option<String>,
+    /// Inference params used when the request does not set them.
+    #[serde(default)]
+    pub params: ManifestParams,
+    /// Extra reference prompts besides the `default` one in `ref.wav` and
+    /// `ref.txt`, paths relative to the voice directory.
+    #[serde(default)]
+    pub emotions: BTreeMap<String, EmotionRef>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestParams {
+    pub top_k: Option<i64>,
+    pub top_p: Option<f32>,
+    pub temperature: Option<f32>,
+    pub speed: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmotionRef {
+    pub ref_audio: PathBuf,
+    pub ref_text: PathBuf,
+}
+
+impl VoiceManifest {
+    /// Reads `voice.toml` from `dir`, a missing file is an empty manifest.
+    pub fn read(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let manifest = toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", MANIFEST_FILE, e))?;
+        Ok(manifest)
+    }
+
+    /// `InferParams::default()` with the voice's defaults applied.
+    pub fn infer_params(&self) -> InferParams {
+        let mut params = InferParams::default();
+        if let Some(top_k) = self.params.top_k {
+            params.top_k = top_k;
+        }
+        if let Some(top_p) = self.params.top_p {
+            params.top_p = top_p;
+        }
+        if let Some(temperature) = self.params.temperature {
+            params.temperature = temperature;
+        }
+        if let Some(speed) = self.params.speed {
+            params.speed = speed;
+        }
+        params
+    }
+
+    /// Emotion names, `default` first.
+    pub fn emotion_names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_EMOTION)
+            .chain(self.emotions.keys().map(|s| s.as_str()))
+            .collect()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct VoiceModel {
     pub name: String,
     pub path: PathBuf,
+    /// Fingerprint of the voice files when the voice was registered.
+    pub fingerprint: Option<u64>,
+    /// Empty when the voice has no `voice.toml` or it cannot be parsed.
+    pub manifest: VoiceManifest,
+}
+
+impl VoiceModel {
+    pub fn new(name: &str, path: PathBuf) -> Self {
+        let manifest = VoiceManifest::read(&path).unwrap_or_default();
+        let mut voice_model = Self {
+            name: name.to_string(),
+            path,
+            fingerprint: None,
+            manifest,
+        };
+        voice_model.fingerprint = voice_model.current_fingerprint();
+        voice_model
+    }
+
+    /// Hash of the sizes and modification times of the voice files, `None`
+    /// while one of them is missing.
+    pub fn current_fingerprint(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        let mut files = vec![
+            self.ref_audio_path(),
+            self.ref_text_path(),
+            self.model_path(),
+        ];
+        let manifest_path = self.path.join(MANIFEST_FILE);
+        if manifest_path.exists() {
+            // emotion files as listed by the manifest on disk now
+            let manifest = VoiceManifest::read(&self.path).unwrap_or_default();
+            for emotion in manifest.emotions.values() {
+                files.push(self.path.join(&emotion.ref_audio));
+                files.push(self.path.join(&emotion.ref_text));
+            }
+            files.push(manifest_path);
+        }
+
+        for file in files {
+            let metadata = fs::metadata(file).ok()?;
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok()?.hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+
+    pub fn ref_audio_path(&self) -> PathBuf {
+        self.path.join(REF_AUDIO_FILE)
+    }
+
+    pub fn ref_text_path(&self) -> PathBuf {
+        self.path.join(REF_TEXT_FILE)
+    }
+
+    pub fn model_path(&self) -> PathBuf {
+        self.path.join(MODEL_FILE)
+    }
+
+    pub fn has_emotion(&self, emotion: &str) -> bool {
+        emotion == DEFAULT_EMOTION || self.manifest.emotions.contains_key(emotion)
+    }
+
+    /// Checks the model, manifest and every reference prompt, and returns the
+    /// references by emotion for `load`, `default` first.
+    pub fn validate(&self) -> anyhow::Result<Vec<(String, VoiceRef)>> {
+        let model_path = self.model_path();
+        match fs::metadata(&model_path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => {}
+            Ok(_) => return Err(anyhow::anyhow!("{} is empty", MODEL_FILE)),
+            Err(e) => return Err(anyhow::anyhow!("{}: {}", MODEL_FILE, e)),
+        }
+
+        let manifest = VoiceManifest::read(&self.path)?;
+        let mut refs = vec![(
+            DEFAULT_EMOTION.to_string(),
+            read_ref(&self.ref_audio_path(), &self.ref_text_path())?,
+        )];
+        for (emotion, emotion_ref) in &manifest.emotions {
+            if emotion == DEFAULT_EMOTION || emotion.is_empty() {
+                return Err(anyhow::anyhow!("invalid emotion name: {:?}", emotion));
+            }
+            let voice_ref = read_ref(
+                &self.path.join(&emotion_ref.ref_audio),
+                &self.path.join(&emotion_ref.ref_text),
+            )
+            .map_err(|e| anyhow::anyhow!("emotion {}: {}", emotion, e))?;
+            refs.push((emotion.clone(), voice_ref));
+        }
+        manifest
+            .infer_params()
+            .validate()
+            .map_err(|e| anyhow::anyhow!("{}: {}", MANIFEST_FILE, e))?;
+
+        Ok(refs)
+    }
+
+    /// Validates the voice and loads its speaker, with one prompt per
+    /// emotion sharing the model. The speaker replaces the old one only once
+    /// every prompt is ready.
+    pub fn load(&self, gpt_sovits: &GPTSovits) -> anyhow::Result<()> {
+        let mut refs = self.validate()?.into_iter();
+
+        let model_path = self.model_path();
+        let model_path = model_path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("invalid model path: {}", model_path.display()))?;
+
+        // validate() always returns the default emotion first
+        let (_, default_ref) = refs
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing {}", REF_AUDIO_FILE))?;
+        let speaker = gpt_sovits.load_speaker(
+            &self.name,
+            model_path,
+            &default_ref.samples,
+            default_ref.sample_rate as usize,
+            &default_ref.text,
+        )?;
+
+        for (emotion, voice_ref) in refs {
+            let prompt = gpt_sovits
+                .build_prompt(
+                    &voice_ref.samples,
+                    voice_ref.sample_rate as usize,
+                    &voice_ref.text,
+                )
+                .map_err(|e| anyhow::anyhow!("emotion {}: {}", emotion, e))?;
+            speaker.add_prompt(&emotion, prompt)?;
+        }
+
+        gpt_sovits.insert_speaker(speaker);
+        Ok(())
+    }
+
+    /// Removes the speaker of this voice.
+    pub fn unload(&self, gpt_sovits: &GPTSovits) {
+        gpt_sovits.remove_speaker(&self.name);
+    }
+}
+
+/// Reads and checks one reference clip and its text.
+fn read_ref(audio_path: &Path, text_path: &Path) -> anyhow::Result<VoiceRef> {
+    let audio_file = file_name(audio_path);
+    let text_file = file_name(text_path);
+
+    let text =
+        fs::read_to_string(text_path).map_err(|e| anyhow::anyhow!("{}: {}", text_file, e))?;
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("{} is empty", text_file));
+    }
+
+    let file = fs::File::open(audio_path).map_err(|e| anyhow::anyhow!("{}: {}", audio_file, e))?;
+    let (head, samples) = wav_io::read_from_file(file)
+        .map_err(|e| anyhow::anyhow!("{} is not a readable WAV: {}", audio_file, e))?;
+    if head.channels != 1 {
+        return Err(anyhow::anyhow!(
+            "{} must be mono, got {} channels",
+            audio_file,
+            head.channels
+        ));
+    }
+    if head.sample_rate < MIN_REF_SAMPLE_RATE {
+        return Err(anyhow::anyhow!(
+            "{} sample rate is {} Hz, need at least {} Hz",
+            audio_file,
+            head.sample_rate,
+            MIN_REF_SAMPLE_RATE
+        ));
+    }
+    let secs = samples.len() as f32 / head.sample_rate as f32;
+    if !(MIN_REF_AUDIO_SECS..=MAX_REF_AUDIO_SECS).contains(&secs) {
+        return Err(anyhow::anyhow!(
+            "{} is {:.1}s long, need {}~{}s",
+            audio_file,
+            secs,
+            MIN_REF_AUDIO_SECS,
+            MAX_REF_AUDIO_SECS
+        ));
+    }
+
+    Ok(VoiceRef {
+        samples,
+        sample_rate: head.sample_rate,
+        text,
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
 }
 
 #[derive(Debug, Clone)]
 pub struct VoiceManager {
     voices_dir: PathBuf,
     voices: HashMap<String, VoiceModel>,
+    errors: HashMap<String, String>,
 }
 
 impl VoiceManager {
@@ -19,12 +338,16 @@ impl VoiceManager {
         Self {
             voices_dir: voices_dir.as_ref().to_path_buf(),
             voices: HashMap::new(),
+            errors: HashMap::new(),
         }
     }
 
+    /// Registers every valid voice directory, invalid ones are skipped and
+    /// their reason kept for `status`.
     pub fn scan_voices(&mut self) -> std::io::Result<()> {
         self.voices.clear();
-        
+        self.errors.clear();
+
         // Create voices directory if it doesn't exist
         if !self.voices_dir.exists() {
             fs::create_dir_all(&self.voices_dir)?;
@@ -34,21 +357,23 @@ impl VoiceManager {
         for entry in fs::read_dir(&self.voices_dir)? {
             let entry = entry?;
             let path = entry.path();
-            
-            if path.is_dir() {
-                let name = path.file_name()
+
+            // hidden directories are staging areas of voices being replaced
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if path.is_dir() && !hidden {
+                let name = path
+                    .file_name()
                     .and_then(|n| n.to_str())
                     .map(|s| s.to_string())
                     .unwrap_or_default();
-                
-                // Check if this directory contains required model files
-                // TODO: Add specific file checks based on your model requirements
-                
-                let voice_model = VoiceModel {
-                    name: name.clone(),
-                    path,
-                };
-                
+
+                let voice_model = VoiceModel::new(&name, path);
+                if let Err(e) = voice_model.validate() {
+                    log::warn!("skip voice {}: {}", name, e);
+                    self.errors.insert(name, e.to_string());
+                    continue;
+                }
+
                 self.voices.insert(name, voice_model);
             }
         }
@@ -63,4 +388,131 @@ impl VoiceManager {
     pub fn list_voices(&self) -> Vec<&str> {
         self.voices.keys().map(|s| s.as_str()).collect()
     }
+
+    pub fn voices_dir(&self) -> &Path {
+        &self.voices_dir
+    }
+
+    /// Directory a voice with this name lives in, whether registered or not.
+    pub fn voice_dir(&self, name: &str) -> PathBuf {
+        self.voices_dir.join(name)
+    }
+
+    /// Registers the voice in `voices_dir/<name>`, replacing any with that name.
+    pub fn add_voice(&mut self, name: &str) -> &VoiceModel {
+        let voice_model = VoiceModel::new(name, self.voice_dir(name));
+        self.errors.remove(name);
+        self.voices.insert(name.to_string(), voice_model);
+        &self.voices[name]
+    }
+
+    pub fn remove_voice(&mut self, name: &str) -> Option<VoiceModel> {
+        self.errors.remove(name);
+        self.voices.remove(name)
+    }
+
+    /// Records why a voice failed to load. A voice that is still registered
+    /// keeps serving with the version loaded before.
+    pub fn set_error(&mut self, name: &str, error: String) {
+        self.errors.insert(name.to_string(), error);
+    }
+
+    /// Unregisters a voice that failed to load and records why.
+    pub fn mark_failed(&mut self, name: &str, error: String) {
+        self.voices.remove(name);
+        self.errors.insert(name.to_string(), error);
+    }
+
+    /// Status of every registered or skipped voice, sorted by name.
+    pub fn status(&self) -> Vec<VoiceStatus> {
+        let mut names: Vec<&String> = self.voices.keys().chain(self.errors.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| VoiceStatus {
+                name: name.clone(),
+                loaded: self.voices.contains_key(name),
+                error: self.errors.get(name).cloned(),
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_voice_manifest() {
+    let manifest: VoiceManifest = toml::from_str(
+        r#"
+        display_name = "小明"
+        [params]
+        top_k = 10
+        [emotions.happy]
+        ref_audio = "happy.wav"
+        ref_text = "happy.txt"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest.display_name.as_deref(), Some("小明"));
+    assert_eq!(manifest.emotion_names(), vec!["default", "happy"]);
+
+    let params = manifest.infer_params();
+    assert_eq!(params.top_k, 10);
+    assert_eq!(params.temperature, InferParams::default().temperature);
+
+    assert!(toml::from_str::<VoiceManifest>("unknown = 1").is_err());
+}
+
+#[test]
+fn test_voice_validate() {
+    use crate::audio::AudioFormat;
+
+    let dir = std::env::temp_dir().join(format!("gpt_sovits_voice_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let write_wav = |name: &str, secs: f32, sample_rate: u32| {
+        let samples = vec![0.1; (secs * sample_rate as f32) as usize];
+        let wav = AudioFormat::Wav16.encode(&samples, sample_rate).unwrap();
+        fs::write(dir.join(name), wav).unwrap();
+    };
+    let voice = || VoiceModel::new("test", dir.clone());
+    let error = || voice().validate().unwrap_err().to_string();
+
+    assert!(error().contains(MODEL_FILE));
+    fs::write(dir.join(MODEL_FILE), b"model").unwrap();
+    assert!(error().contains(REF_TEXT_FILE));
+    fs::write(dir.join(REF_TEXT_FILE), "  \n").unwrap();
+    assert!(error().contains("is empty"));
+    fs::write(dir.join(REF_TEXT_FILE), "参考文本。").unwrap();
+
+    write_wav(REF_AUDIO_FILE, 1.0, 32000);
+    assert!(error().contains("long"));
+    write_wav(REF_AUDIO_FILE, 5.0, 8000);
+    assert!(error().contains("sample rate"));
+    write_wav(REF_AUDIO_FILE, 5.0, 32000);
+    let refs = voice().validate().unwrap();
+    assert_eq!(refs.len(), 1);
+    assert_eq!(refs[0].0, DEFAULT_EMOTION);
+    assert_eq!(refs[0].1.sample_rate, 32000);
+
+    // emotions from voice.toml are validated too
+    fs::write(
+        dir.join(MANIFEST_FILE),
+        "[emotions.happy]\nref_audio = \"happy.wav\"\nref_text = \"happy.txt\"\n",
+    )
+    .unwrap();
+    assert!(error().starts_with("emotion happy"));
+    write_wav("happy.wav", 4.0, 44100);
+    fs::write(dir.join("happy.txt"), "开心的参考文本。").unwrap();
+    let emotions: Vec<String> = voice()
+        .validate()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.0)
+        .collect();
+    assert_eq!(emotions, vec!["default", "happy"]);
+
+    fs::write(dir.join(MANIFEST_FILE), "[params]\ntop_k = 0\n").unwrap();
+    assert!(error().contains(MANIFEST_FILE));
+
+    fs::remove_dir_all(&dir).unwrap();
 }
@@ -0,0 +1,127 @@
+// 监听 voices 目录：新增或修改的声音在后台加载后替换，删除的声音立即注销
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use gpt_sovits_rs::voice_manager::{VoiceManager, VoiceModel};
+use gpt_sovits_rs::GPTSovits;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+// 目录静止这么久之后才处理，避免文件复制到一半就开始加载
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// 返回的 watcher 需要一直持有，drop 后停止监听
+pub fn watch_voices(
+    gpt_sovits: Arc<GPTSovits>,
+    voice_manager: Arc<RwLock<VoiceManager>>,
+) -> notify::Result<RecommendedWatcher> {
+    let voices_dir = voice_manager.read().unwrap().voices_dir().canonicalize()?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&voices_dir, RecursiveMode::Recursive)?;
+    log::info!("开始监听声音目录: {}", voices_dir.display());
+
+    std::thread::Builder::new()
+        .name("voice-watcher".to_string())
+        .spawn(move || {
+            let mut pending: HashSet<String> = HashSet::new();
+            loop {
+                match rx.recv_timeout(DEBOUNCE) {
+                    Ok(Ok(event)) => {
+                        pending.extend(
+                            event
+                                .paths
+                                .iter()
+                                .filter_map(|path| voice_name(&voices_dir, path)),
+                        );
+                    }
+                    Ok(Err(e)) => log::warn!("监听声音目录出错: {}", e),
+                    Err(RecvTimeoutError::Timeout) => {
+                        for name in pending.drain() {
+                            reload_voice(&gpt_sovits, &voice_manager, &name);
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            log::info!("声音目录监听已停止");
+        })?;
+
+    Ok(watcher)
+}
+
+// voices/<name>/... 中的 <name>，隐藏目录（管理接口的临时目录）不处理
+fn voice_name(voices_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(voices_dir).ok()?;
+    match relative.components().next()? {
+        Component::Normal(name) => {
+            let name = name.to_str()?;
+            (!name.starts_with('.')).then(|| name.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn reload_voice(gpt_sovits: &GPTSovits, voice_manager: &RwLock<VoiceManager>, name: &str) {
+    let (dir, registered): (PathBuf, Option<VoiceModel>) = {
+        let voice_manager = voice_manager.read().unwrap();
+        (
+            voice_manager.voice_dir(name),
+            voice_manager.get_voice(name).cloned(),
+        )
+    };
+
+    // 目录被删除：注销声音，正在进行的请求持有旧的 Speaker，不受影响
+    if !dir.is_dir() {
+        if let Some(registered) = registered {
+            voice_manager.write().unwrap().remove_voice(name);
+            registered.unload(gpt_sovits);
+            log::info!("声音目录已删除，注销声音: {}", name);
+        }
+        return;
+    }
+
+    let voice_model = VoiceModel::new(name, dir);
+    if voice_model.fingerprint.is_some()
+        && registered.and_then(|v| v.fingerprint) == voice_model.fingerprint
+    {
+        log::debug!("声音 {} 没有变化", name);
+        return;
+    }
+
+    // 校验并加载全部情感后才替换旧的 Speaker，失败时保留旧的并记录原因
+    log::info!("加载声音: {}", name);
+    match voice_model.load(gpt_sovits) {
+        Ok(()) => {
+            voice_manager.write().unwrap().add_voice(name);
+            log::info!("声音已更新: {}", name);
+        }
+        Err(e) => {
+            log::error!("加载声音 {} 失败: {}", name, e);
+            voice_manager
+                .write()
+                .unwrap()
+                .set_error(name, e.to_string());
+        }
+    }
+}
+
+#[test]
+fn test_voice_name() {
+    let voices_dir = Path::new("/srv/voices");
+    let name = |path: &str| voice_name(voices_dir, Path::new(path));
+
+    assert_eq!(name("/srv/voices/alice"), Some("alice".to_string()));
+    assert_eq!(name("/srv/voices/alice/ref.wav"), Some("alice".to_string()));
+    assert_eq!(
+        name("/srv/voices/alice/happy/ref.txt"),
+        Some("alice".to_string())
+    );
+    // staging directories of the admin endpoints and paths outside voices/
+    assert_eq!(name("/srv/voices/.alice.new/ref.wav"), None);
+    assert_eq!(name("/srv/voices"), None);
+    assert_eq!(name("/srv/other/alice"), None);
+}